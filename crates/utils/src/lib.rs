@@ -2,6 +2,8 @@ use ae::sys::{PF_Pixel, PF_PixelFloat};
 use ae::{Pixel8, Pixel16, PixelF32};
 use after_effects as ae;
 
+pub mod spectral;
+
 pub trait ToPixel {
     fn to_pixel32(&self) -> PixelF32;
     fn to_pixel16(&self) -> Pixel16;
@@ -101,3 +103,160 @@ impl ToPixel for PixelF32 {
         }
     }
 }
+
+/// Gaussian blur of a single-channel `width` x `height` buffer, applied as two
+/// 1D passes with clamp-to-edge sampling. `sigma <= 0` returns a copy.
+pub fn gaussian_2d_separable(src: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    if !sigma.is_finite() || sigma <= 0.0 || width == 0 || height == 0 {
+        return src.to_vec();
+    }
+
+    let radius = (sigma * 3.0).ceil() as isize;
+    let denom = 2.0 * sigma * sigma;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / denom).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    for k in &mut kernel {
+        *k /= total;
+    }
+
+    let max_x = width as isize - 1;
+    let max_y = height as isize - 1;
+    let mut tmp = vec![0.0f32; width * height];
+    for y in 0..height {
+        let row = y * width;
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let sx = (x as isize + k as isize - radius).clamp(0, max_x) as usize;
+                acc += src[row + sx] * weight;
+            }
+            tmp[row + x] = acc;
+        }
+    }
+
+    let mut out = vec![0.0f32; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let sy = (y as isize + k as isize - radius).clamp(0, max_y) as usize;
+                acc += tmp[sy * width + x] * weight;
+            }
+            out[y * width + x] = acc;
+        }
+    }
+    out
+}
+
+/// Above this sigma `gaussian_blur` switches to iterated box blurs, whose cost
+/// does not grow with the radius.
+pub const GAUSSIAN_DIRECT_MAX_SIGMA: f32 = 8.0;
+
+/// Gaussian blur of a single-channel buffer at any sigma: the exact separable
+/// kernel for small sigmas, three box blurs of matching variance above
+/// [`GAUSSIAN_DIRECT_MAX_SIGMA`]. Edges clamp in both cases.
+pub fn gaussian_blur(src: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    if !sigma.is_finite() || sigma <= GAUSSIAN_DIRECT_MAX_SIGMA {
+        return gaussian_2d_separable(src, width, height, sigma);
+    }
+    let mut out = src.to_vec();
+    let mut tmp = vec![0.0f32; src.len()];
+    for radius in box_radii_for_gauss(sigma) {
+        box_blur_rows(&out, &mut tmp, width, height, radius);
+        box_blur_cols(&tmp, &mut out, width, height, radius);
+    }
+    out
+}
+
+// Radii of three box blurs whose summed variance approximates `sigma`
+// (Kovesi, "Fast almost-Gaussian filtering").
+fn box_radii_for_gauss(sigma: f32) -> [usize; 3] {
+    const PASSES: f32 = 3.0;
+    let ideal = (12.0 * sigma * sigma / PASSES + 1.0).sqrt();
+    let mut lower = ideal.floor() as i64;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let lw = lower as f32;
+    let upper_passes = (12.0 * sigma * sigma - PASSES * lw * lw - 4.0 * PASSES * lw - 3.0 * PASSES)
+        / (-4.0 * lw - 4.0);
+    let m = upper_passes.round() as usize;
+    std::array::from_fn(|i| {
+        let width = if i < m { lower } else { lower + 2 };
+        (width.max(1) as usize - 1) / 2
+    })
+}
+
+// Running-sum box blur along rows with clamp-to-edge sampling.
+fn box_blur_rows(src: &[f32], dst: &mut [f32], width: usize, height: usize, radius: usize) {
+    let r = radius as isize;
+    let max_x = width as isize - 1;
+    let scale = 1.0 / (2 * radius + 1) as f32;
+    for y in 0..height {
+        let row = &src[y * width..(y + 1) * width];
+        let at = |x: isize| row[x.clamp(0, max_x) as usize];
+        let mut acc: f32 = (-r..=r).map(at).sum();
+        for x in 0..width as isize {
+            dst[y * width + x as usize] = acc * scale;
+            acc += at(x + r + 1) - at(x - r);
+        }
+    }
+}
+
+// Running-sum box blur along columns with clamp-to-edge sampling.
+fn box_blur_cols(src: &[f32], dst: &mut [f32], width: usize, height: usize, radius: usize) {
+    let r = radius as isize;
+    let max_y = height as isize - 1;
+    let scale = 1.0 / (2 * radius + 1) as f32;
+    for x in 0..width {
+        let at = |y: isize| src[y.clamp(0, max_y) as usize * width + x];
+        let mut acc: f32 = (-r..=r).map(at).sum();
+        for y in 0..height as isize {
+            dst[y as usize * width + x] = acc * scale;
+            acc += at(y + r + 1) - at(y - r);
+        }
+    }
+}
+
+/// Integer hash with good avalanche (lowbias32). Pure integer math, so the
+/// same input gives the same bits on every machine and bit depth.
+pub fn hash_u32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    x
+}
+
+/// Maps a hash to `0..=1`.
+pub fn rand01(h: u32) -> f32 {
+    h as f32 / u32::MAX as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_blur_gaussian_tracks_the_exact_kernel() {
+        let (w, h) = (96, 1);
+        let step: Vec<f32> = (0..w).map(|x| if x < w / 2 { 0.0 } else { 1.0 }).collect();
+        let sigma = 12.0;
+        let exact = gaussian_2d_separable(&step, w, h, sigma);
+        let boxed = gaussian_blur(&step, w, h, sigma);
+        for (got, want) in boxed.iter().zip(&exact) {
+            assert!((got - want).abs() < 0.03, "got {got}, want {want}");
+        }
+    }
+
+    #[test]
+    fn box_blur_gaussian_keeps_flat_images_flat() {
+        let flat = vec![0.25f32; 20 * 10];
+        for v in gaussian_blur(&flat, 20, 10, 40.0) {
+            assert!((v - 0.25).abs() < 1.0e-5);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Dependency-free 2D FFT on power-of-two planes.
+
+/// Complex spectrum of a `width` x `height` plane, row-major.
+#[derive(Clone)]
+pub struct Spectrum {
+    pub width: usize,
+    pub height: usize,
+    pub re: Vec<f32>,
+    pub im: Vec<f32>,
+}
+
+impl Spectrum {
+    /// Pointwise complex product, i.e. convolution in the spatial domain.
+    pub fn multiply(&mut self, other: &Spectrum) {
+        for i in 0..self.re.len() {
+            let (a, b) = (self.re[i], self.im[i]);
+            let (c, d) = (other.re[i], other.im[i]);
+            self.re[i] = a * c - b * d;
+            self.im[i] = a * d + b * c;
+        }
+    }
+}
+
+/// Forward 2D FFT of a real `width` x `height` plane. Sizes that are not powers
+/// of two are zero-padded on the right and bottom; the spectrum keeps the padded
+/// size.
+///
+/// # Panics
+///
+/// If `plane.len()` is not `width * height`.
+pub fn fft2(plane: &[f32], width: usize, height: usize) -> Spectrum {
+    assert_eq!(plane.len(), width * height, "plane does not match its size");
+    let (pw, ph) = (width.next_power_of_two(), height.next_power_of_two());
+    let mut re = vec![0.0; pw * ph];
+    for (dst, src) in re
+        .chunks_exact_mut(pw)
+        .zip(plane.chunks_exact(width.max(1)))
+    {
+        dst[..width].copy_from_slice(src);
+    }
+    let mut spectrum = Spectrum {
+        width: pw,
+        height: ph,
+        re,
+        im: vec![0.0; pw * ph],
+    };
+    transform_2d(&mut spectrum, false);
+    spectrum
+}
+
+/// Forward 2D FFT of each channel of an interleaved RGBA buffer.
+pub fn fft2_rgba(rgba: &[f32], width: usize, height: usize) -> [Spectrum; 4] {
+    std::array::from_fn(|c| {
+        let plane: Vec<f32> = rgba.iter().skip(c).step_by(4).copied().collect();
+        fft2(&plane, width, height)
+    })
+}
+
+/// Inverse 2D FFT, returning the real part scaled by `1 / (width * height)`.
+///
+/// # Panics
+///
+/// If the spectrum's sizes are not powers of two or do not match its buffers.
+pub fn ifft2(mut spectrum: Spectrum) -> Vec<f32> {
+    transform_2d(&mut spectrum, true);
+    let scale = 1.0 / (spectrum.width * spectrum.height).max(1) as f32;
+    spectrum.re.iter().map(|v| v * scale).collect()
+}
+
+fn transform_2d(spectrum: &mut Spectrum, inverse: bool) {
+    let (w, h) = (spectrum.width, spectrum.height);
+    assert!(
+        w.is_power_of_two() && h.is_power_of_two(),
+        "spectrum size {w}x{h} is not a power of two"
+    );
+    assert!(
+        spectrum.re.len() == w * h && spectrum.im.len() == w * h,
+        "spectrum buffers do not match its size"
+    );
+    for row in 0..h {
+        let range = row * w..(row + 1) * w;
+        fft_in_place(
+            &mut spectrum.re[range.clone()],
+            &mut spectrum.im[range],
+            inverse,
+        );
+    }
+
+    let mut col_re = vec![0.0f32; h];
+    let mut col_im = vec![0.0f32; h];
+    for col in 0..w {
+        for row in 0..h {
+            col_re[row] = spectrum.re[row * w + col];
+            col_im[row] = spectrum.im[row * w + col];
+        }
+        fft_in_place(&mut col_re, &mut col_im, inverse);
+        for row in 0..h {
+            spectrum.re[row * w + col] = col_re[row];
+            spectrum.im[row * w + col] = col_im[row];
+        }
+    }
+}
+
+// Iterative radix-2 Cooley-Tukey, unscaled in both directions.
+fn fft_in_place(re: &mut [f32], im: &mut [f32], inverse: bool) {
+    let n = re.len();
+    if n < 2 {
+        return;
+    }
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * std::f64::consts::TAU / len as f64;
+        let half = len / 2;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                // Twiddles in f64 keep large transforms from drifting.
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (wr, wi) = (cos as f32, sin as f32);
+                let (a, b) = (start + k, start + k + half);
+                let tr = re[b] * wr - im[b] * wi;
+                let ti = re[b] * wi + im[b] * wr;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(got: f32, want: f32) {
+        assert!((got - want).abs() < 1.0e-3, "got {got}, want {want}");
+    }
+
+    #[test]
+    fn round_trip_restores_the_plane() {
+        let (w, h) = (16, 8);
+        let plane: Vec<f32> = (0..w * h).map(|i| ((i * 37 % 23) as f32).sin()).collect();
+        let restored = ifft2(fft2(&plane, w, h));
+        for (got, want) in restored.iter().zip(&plane) {
+            assert_close(*got, *want);
+        }
+    }
+
+    #[test]
+    fn odd_sizes_are_zero_padded() {
+        let (w, h) = (5, 3);
+        let plane: Vec<f32> = (0..w * h).map(|i| i as f32).collect();
+        let spectrum = fft2(&plane, w, h);
+        assert_eq!((spectrum.width, spectrum.height), (8, 4));
+        let restored = ifft2(spectrum);
+        for y in 0..4 {
+            for x in 0..8 {
+                let want = if x < w && y < h {
+                    plane[y * w + x]
+                } else {
+                    0.0
+                };
+                assert_close(restored[y * 8 + x], want);
+            }
+        }
+    }
+
+    #[test]
+    fn impulse_has_a_flat_spectrum() {
+        let mut plane = vec![0.0; 4 * 4];
+        plane[0] = 1.0;
+        let spectrum = fft2(&plane, 4, 4);
+        for i in 0..16 {
+            assert_close(spectrum.re[i], 1.0);
+            assert_close(spectrum.im[i], 0.0);
+        }
+    }
+
+    #[test]
+    fn cosine_lands_in_its_two_bins() {
+        // cos(2*pi*x/4) on both rows: w * h / 2 = 8 in bins 2 and 6 of row 0.
+        let (w, h) = (8, 2);
+        let plane: Vec<f32> = (0..w * h)
+            .map(|i| (std::f32::consts::TAU * (i % w) as f32 / 4.0).cos())
+            .collect();
+        let spectrum = fft2(&plane, w, h);
+        for i in 0..w * h {
+            let want = if i == 2 || i == 6 { 8.0 } else { 0.0 };
+            assert_close(spectrum.re[i], want);
+            assert_close(spectrum.im[i], 0.0);
+        }
+    }
+
+    #[test]
+    fn multiply_is_circular_convolution() {
+        let plane = [1.0, 2.0, 3.0, 4.0];
+        let mut shift = [0.0; 4];
+        shift[1] = 1.0;
+        let mut spectrum = fft2(&plane, 4, 1);
+        spectrum.multiply(&fft2(&shift, 4, 1));
+        let shifted = ifft2(spectrum);
+        for (got, want) in shifted.iter().zip([4.0, 1.0, 2.0, 3.0]) {
+            assert_close(*got, want);
+        }
+    }
+}
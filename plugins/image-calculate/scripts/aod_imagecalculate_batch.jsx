@@ -0,0 +1,175 @@
+// aod_imagecalculate_batch.jsx
+//
+// Applies AOD_ImageCalculate to every selected layer in the active
+// composition. If one of the selected layers already carries the effect, its
+// current settings are copied onto the others; otherwise the effect is added
+// with its defaults.
+//
+// Batch operations:
+//   Apply Effect  - add / update the effect on the selected layers only.
+//   Render Frame  - additionally queue the current frame in the Render Queue.
+//   Export Frame  - additionally save the current frame as a PNG.
+//
+// This lives in a script rather than behind a param on the effect itself:
+// an effect's command handler runs on render and UI threads where adding
+// effects to other layers or driving the Render Queue through AEGP is not
+// safe.
+//
+// Run via File > Scripts > Run Script File..., or copy it into the
+// "Scripts/ScriptUI Panels" folder.
+
+(function aodImageCalculateBatch(thisObj) {
+    var SCRIPT_NAME = "AOD ImageCalculate Batch";
+    var MATCH_NAME = "ImageCalculate";
+    var BATCH_OPS = ["Apply Effect", "Render Frame", "Export Frame"];
+
+    function findEffect(layer) {
+        var effects = layer.property("ADBE Effect Parade");
+        if (effects === null) {
+            return null;
+        }
+        for (var i = 1; i <= effects.numProperties; i++) {
+            if (effects.property(i).matchName === MATCH_NAME) {
+                return effects.property(i);
+            }
+        }
+        return null;
+    }
+
+    function canCopy(prop) {
+        return prop.propertyType === PropertyType.PROPERTY
+            && prop.propertyValueType !== PropertyValueType.NO_VALUE
+            && prop.propertyValueType !== PropertyValueType.CUSTOM_VALUE
+            && !prop.elided;
+    }
+
+    // Copies the source values at `time` onto `dst`. Keyframed destination
+    // streams get a key at `time` so existing animation is kept.
+    function copySettings(src, dst, time) {
+        var count = Math.min(src.numProperties, dst.numProperties);
+        for (var i = 1; i <= count; i++) {
+            var s = src.property(i);
+            var d = dst.property(i);
+            if (s.propertyType !== PropertyType.PROPERTY) {
+                copySettings(s, d, time);
+                continue;
+            }
+            if (!canCopy(s) || !canCopy(d)) {
+                continue;
+            }
+            try {
+                var value = s.valueAtTime(time, true);
+                if (d.numKeys > 0) {
+                    d.setValueAtTime(time, value);
+                } else {
+                    d.setValue(value);
+                }
+            } catch (e) {
+                // Hidden or disabled streams reject writes; keep going.
+            }
+        }
+    }
+
+    function applyToSelection(comp, progress) {
+        var layers = comp.selectedLayers;
+        var source = null;
+        for (var i = 0; i < layers.length && source === null; i++) {
+            source = findEffect(layers[i]);
+        }
+
+        var applied = 0;
+        for (var j = 0; j < layers.length; j++) {
+            var layer = layers[j];
+            var effects = layer.property("ADBE Effect Parade");
+            if (effects === null) {
+                continue;
+            }
+            var effect = findEffect(layer);
+            if (effect === null) {
+                if (!effects.canAddProperty(MATCH_NAME)) {
+                    continue;
+                }
+                effect = effects.addProperty(MATCH_NAME);
+            }
+            if (source !== null && effect !== source) {
+                copySettings(source, effect, comp.time);
+            }
+            applied++;
+            progress.value = ((j + 1) / layers.length) * 100;
+        }
+        return applied;
+    }
+
+    function queueFrame(comp) {
+        var item = app.project.renderQueue.items.add(comp);
+        item.timeSpanStart = comp.time;
+        item.timeSpanDuration = comp.frameDuration;
+        return item;
+    }
+
+    function exportFrame(comp) {
+        var file = File.saveDialog("Export Frame", "PNG:*.png");
+        if (file === null) {
+            return false;
+        }
+        comp.saveFrameToPng(comp.time, file);
+        return true;
+    }
+
+    function run(opIndex, progress) {
+        var comp = app.project.activeItem;
+        if (!(comp instanceof CompItem)) {
+            alert("Open a composition first.", SCRIPT_NAME);
+            return;
+        }
+        if (comp.selectedLayers.length === 0) {
+            alert("Select one or more layers.", SCRIPT_NAME);
+            return;
+        }
+
+        app.beginUndoGroup(SCRIPT_NAME);
+        try {
+            var applied = applyToSelection(comp, progress);
+            if (applied === 0) {
+                alert("None of the selected layers accept effects.", SCRIPT_NAME);
+                return;
+            }
+            if (opIndex === 1) {
+                queueFrame(comp);
+            } else if (opIndex === 2) {
+                exportFrame(comp);
+            }
+        } finally {
+            app.endUndoGroup();
+        }
+    }
+
+    function buildUi(host) {
+        var win = host instanceof Panel
+            ? host
+            : new Window("palette", SCRIPT_NAME, undefined, { resizeable: true });
+        win.orientation = "column";
+        win.alignChildren = ["fill", "top"];
+
+        var row = win.add("group");
+        row.add("statictext", undefined, "Batch Op:");
+        var op = row.add("dropdownlist", undefined, BATCH_OPS);
+        op.selection = 0;
+
+        var progress = win.add("progressbar", undefined, 0, 100);
+        var apply = win.add("button", undefined, "Apply to Selected Layers");
+        apply.onClick = function () {
+            progress.value = 0;
+            run(op.selection.index, progress);
+        };
+
+        win.layout.layout(true);
+        return win;
+    }
+
+    var ui = buildUi(thisObj);
+    if (ui instanceof Window) {
+        ui.center();
+        ui.show();
+    }
+})(this);
@@ -2,9 +2,12 @@
 
 use after_effects as ae;
 use std::env;
+use std::sync::Mutex;
 
 use ae::pf::*;
-use utils::ToPixel;
+use palette::{FromColor, LinSrgb, Oklab, Srgb};
+use utils::spectral::{Spectrum, fft2, ifft2};
+use utils::{ToPixel, gaussian_blur, hash_u32, rand01};
 
 #[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
 enum Params {
@@ -18,15 +21,89 @@ enum Params {
     Epsilon,
     ClampResult,
     UseOriginalAlpha,
+    RandomSeed,
+    AnimateRandom,
+    OperandFit,
+    OutsideA,
+    KeyColor,
+    LumaStandard,
+    KernelPreset,
+    KernelCell00,
+    KernelCell01,
+    KernelCell02,
+    KernelCell10,
+    KernelCell11,
+    KernelCell12,
+    KernelCell20,
+    KernelCell21,
+    KernelCell22,
+    NormalizeKernel,
+    SpectralMixMode,
+    CrossoverSoftness,
+    FeedbackMode,
+    FeedbackDecay,
+    PostAdjustGroupStart,
+    PostAdjustGroupEnd,
+    GainR,
+    OffsetR,
+    GainG,
+    OffsetG,
+    GainB,
+    OffsetB,
+    GainA,
+    OffsetA,
+    ResultMix,
+    MixSpace,
+    DetailRadius,
 }
 
-#[derive(Clone, Copy)]
+// (gain, offset) pairs in R, G, B, A order.
+const POST_ADJUST_PARAMS: [(Params, Params); 4] = [
+    (Params::GainR, Params::OffsetR),
+    (Params::GainG, Params::OffsetG),
+    (Params::GainB, Params::OffsetB),
+    (Params::GainA, Params::OffsetA),
+];
+
+#[derive(Clone, Copy, Debug)]
 enum InputSource {
     Value,
     Layer,
+    Random,
+}
+
+#[derive(Clone, Copy)]
+enum OperandFit {
+    ClampEdge,
+    Tile,
+    Mirror,
+    Stretch,
+    Center,
+}
+
+// How A is read where the output extends past it (SmartRender only).
+#[derive(Clone, Copy)]
+enum OutsideA {
+    Transparent,
+    ClampEdge,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+// Space the final result is blended back toward A in.
+#[derive(Clone, Copy)]
+enum MixSpace {
+    Linear,
+    Oklab,
+}
+
+#[derive(Clone, Copy)]
+enum LumaStandard {
+    Rec709,
+    Rec601,
+    Rec2020,
+    Average,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum MathOp {
     Add,
     Subtract,
@@ -67,8 +144,46 @@ enum MathOp {
     HyperbolicTangent,
     ToRadians,
     ToDegrees,
+    ChromaKey,
+    Mix,
+    Select,
+    ClampRange,
+    CrossCorrelation,
+    FreqMatch,
+    ConvMatrix,
+    HistogramEqualize,
+    GradientX,
+    GradientY,
+    GradientMagnitude,
+    Laplacian,
+    FreqConvolve,
+    SpectralMix,
+    StyleMatch,
+}
+
+// Which layer supplies each band in Spectral Mix. The cross-fade modes keep the
+// named layer's low band and average both layers above the crossover.
+#[derive(Clone, Copy)]
+enum SpectralMixMode {
+    LowAHighB,
+    LowBHighA,
+    CrossFadeA,
+    CrossFadeB,
 }
 
+// Row-major 3x3 kernel cells, K00..K22.
+const KERNEL_CELLS: [Params; 9] = [
+    Params::KernelCell00,
+    Params::KernelCell01,
+    Params::KernelCell02,
+    Params::KernelCell10,
+    Params::KernelCell11,
+    Params::KernelCell12,
+    Params::KernelCell20,
+    Params::KernelCell21,
+    Params::KernelCell22,
+];
+
 struct OperationUiInfo {
     expression: &'static str,
     b_label: &'static str,
@@ -80,11 +195,239 @@ struct Plugin {
     aegp_id: Option<ae::aegp::PluginId>,
 }
 
-ae::define_effect!(Plugin, (), Params);
+// Per-instance state kept in sequence data. The feedback history only lives
+// for the session; the flattened version records the params layout.
+struct Instance {
+    feedback: Mutex<FeedbackHistory>,
+    // PARAMS_LAYOUT the saved popup values were written with.
+    params_layout: u16,
+}
+
+// An instance that is neither freshly applied (SequenceSetup) nor read back from
+// sequence data comes from a project saved by 0.1.0, which kept none.
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            feedback: Mutex::default(),
+            params_layout: LEGACY_PARAMS_LAYOUT,
+        }
+    }
+}
+
+// Popup layout of the 0.1.0 release, whose effect kept no sequence data.
+const LEGACY_PARAMS_LAYOUT: u16 = 1;
+// Current popup layout, flattened as the sequence data version. Bump it when a
+// popup's options change other than by appending, and add a remap for it.
+const PARAMS_LAYOUT: u16 = 2;
+
+// Saved popup indices that moved: values of `id` saved with a layout older than
+// `before` are looked up in `map` (old index - 1 -> new index). Options have
+// only been appended so far, so no popup needs one yet.
+struct PopupRemap {
+    before: u16,
+    id: Params,
+    map: &'static [i32],
+}
+
+const POPUP_REMAPS: &[PopupRemap] = &[];
+
+// Current popup index of `value`, saved with params `layout`.
+fn remap_popup(layout: u16, id: Params, value: i32) -> i32 {
+    POPUP_REMAPS
+        .iter()
+        .filter(|r| r.id == id && layout < r.before)
+        .fold(value, |v, r| {
+            usize::try_from(v - 1)
+                .ok()
+                .and_then(|i| r.map.get(i))
+                .copied()
+                .unwrap_or(v)
+        })
+}
+
+// Popups that existed in the legacy layout and may need remapping.
+const LEGACY_POPUPS: [Params; 3] = [
+    Params::Operation,
+    Params::InputBSource,
+    Params::InputCSource,
+];
+
+// Recent Feedback mode outputs, keyed by the frame time they were rendered for.
+#[derive(Default)]
+struct FeedbackHistory {
+    frames: Vec<FeedbackFrame>,
+}
+
+struct FeedbackFrame {
+    time: i32,
+    time_scale: u32,
+    width: usize,
+    height: usize,
+    // RGBA interleaved.
+    data: Vec<f32>,
+}
+
+// Enough for a re-render or a short scrub back without restarting the loop.
+const FEEDBACK_FRAMES: usize = 4;
+
+impl FeedbackHistory {
+    // Output of the frame one step before `time`, if it was rendered at this size.
+    fn previous(
+        &self,
+        (time, step, scale): (i32, i32, u32),
+        w: usize,
+        h: usize,
+    ) -> Option<Vec<f32>> {
+        if step <= 0 {
+            return None;
+        }
+        self.frames
+            .iter()
+            .find(|f| {
+                f.time == time - step && f.time_scale == scale && f.width == w && f.height == h
+            })
+            .map(|f| f.data.clone())
+    }
+
+    fn store(&mut self, (time, _, scale): (i32, i32, u32), w: usize, h: usize, data: Vec<f32>) {
+        self.frames
+            .retain(|f| f.time != time || f.time_scale != scale);
+        if self.frames.len() >= FEEDBACK_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(FeedbackFrame {
+            time,
+            time_scale: scale,
+            width: w,
+            height: h,
+            data,
+        });
+    }
+}
+
+fn lock_feedback(feedback: &Mutex<FeedbackHistory>) -> std::sync::MutexGuard<'_, FeedbackHistory> {
+    match feedback.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+ae::define_effect!(Plugin, Instance, Params);
 
 const PLUGIN_DESCRIPTION: &str =
     "Applies Blender-style math operations to one or two input layers.";
 
+impl Instance {
+    // Popup `id` as the current layout reads it.
+    fn popup(&self, params: &Parameters<Params>, id: Params) -> Result<i32, Error> {
+        let value = params.get(id)?.as_popup()?.value();
+        Ok(remap_popup(self.params_layout, id, value))
+    }
+
+    // Rewrites remapped popups to their current indices so the project is saved
+    // with the current layout.
+    fn migrate_params(&mut self, params: &mut Parameters<Params>) -> Result<(), Error> {
+        for id in LEGACY_POPUPS {
+            let value = params.get(id)?.as_popup()?.value();
+            let remapped = remap_popup(self.params_layout, id, value);
+            if remapped != value {
+                let mut p = params.get_mut(id)?;
+                p.as_popup_mut()?.set_value(remapped);
+                p.set_value_changed();
+            }
+        }
+        self.params_layout = PARAMS_LAYOUT;
+        Ok(())
+    }
+}
+
+impl AdobePluginInstance for Instance {
+    fn flatten(&self) -> Result<(u16, Vec<u8>), Error> {
+        Ok((self.params_layout, Vec::new()))
+    }
+
+    fn unflatten(version: u16, _serialized: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            params_layout: version.clamp(LEGACY_PARAMS_LAYOUT, PARAMS_LAYOUT),
+            ..Self::default()
+        })
+    }
+
+    fn render(&self, _: &mut PluginState, _: &Layer, _: &mut Layer) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn do_dialog(&mut self, _: &mut PluginState) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Rendering lives here so Feedback mode reads this instance's history.
+    fn handle_command(&mut self, plugin: &mut PluginState, cmd: ae::Command) -> Result<(), Error> {
+        match cmd {
+            ae::Command::SequenceSetup => {
+                self.params_layout = PARAMS_LAYOUT;
+            }
+            // Param values may only change here, so an old project is migrated on
+            // its first edit; until then rendering remaps the saved values.
+            ae::Command::UserChangedParam { .. } if self.params_layout < PARAMS_LAYOUT => {
+                self.migrate_params(plugin.params)?;
+            }
+            ae::Command::Render {
+                in_layer,
+                out_layer,
+            } => {
+                plugin.global.do_render(
+                    plugin.in_data,
+                    in_layer,
+                    out_layer,
+                    plugin.params,
+                    None,
+                    self,
+                )?;
+            }
+            ae::Command::SmartRender { extra } => {
+                let cb = extra.callbacks();
+                let in_layer_opt = cb.checkout_layer_pixels(0)?;
+                let out_layer_opt = cb.checkout_output()?;
+
+                let [b, c] = operand_checkouts(plugin.params)?.map(|(_, checkout_id, needed)| {
+                    if needed {
+                        cb.checkout_layer_pixels(checkout_id).ok().flatten()
+                    } else {
+                        None
+                    }
+                });
+                let checked_out = [
+                    (LAYER_B_CHECKOUT_ID, b.is_some()),
+                    (LAYER_C_CHECKOUT_ID, c.is_some()),
+                ];
+
+                let extent = extra.pre_render_data::<Extent>().copied();
+                if let (Some(in_layer), Some(out_layer)) = (in_layer_opt, out_layer_opt) {
+                    let smart = Some(([b, c], extent));
+                    plugin.global.do_render(
+                        plugin.in_data,
+                        in_layer,
+                        out_layer,
+                        plugin.params,
+                        smart,
+                        self,
+                    )?;
+                }
+
+                for (checkout_id, held) in checked_out {
+                    if held {
+                        cb.checkin_layer_pixels(checkout_id)?;
+                    }
+                }
+                cb.checkin_layer_pixels(0)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
 impl AdobePluginGlobal for Plugin {
     fn params_setup(
         &self,
@@ -92,6 +435,10 @@ impl AdobePluginGlobal for Plugin {
         _in_data: InData,
         _: OutData,
     ) -> Result<(), Error> {
+        // Projects store params by registration index and popups by 1-based
+        // option index. New params go after the original ten (ending at Use
+        // Original Alpha) and new options are appended; inserting either would
+        // shift the values of saved projects.
         params.add_with_flags(
             Params::Operation,
             "Operation",
@@ -136,6 +483,21 @@ impl AdobePluginGlobal for Plugin {
                     "Hyperbolic Tangent",
                     "To Radians",
                     "To Degrees",
+                    "Chroma Key",
+                    "Mix",
+                    "Select",
+                    "Clamp",
+                    "Cross-Correlation",
+                    "Frequency Match",
+                    "Convolution Matrix",
+                    "Histogram Equalize",
+                    "Gradient X",
+                    "Gradient Y",
+                    "Gradient Magnitude",
+                    "Laplacian",
+                    "Frequency Convolve",
+                    "Spectral Mix",
+                    "Style Match",
                 ]);
                 d.set_default(1);
             }),
@@ -147,7 +509,7 @@ impl AdobePluginGlobal for Plugin {
             Params::InputBSource,
             "Input B (Operand)",
             PopupDef::setup(|d| {
-                d.set_options(&["Value", "Layer"]);
+                d.set_options(&["Value", "Layer", "Random"]);
                 d.set_default(1);
             }),
             ae::ParamFlag::SUPERVISE,
@@ -216,12 +578,240 @@ impl AdobePluginGlobal for Plugin {
             }),
         )?;
 
-        params.add(
+        params.add_with_flags(
             Params::UseOriginalAlpha,
             "Use Original Alpha",
             CheckBoxDef::setup(|d| {
                 d.set_default(false);
             }),
+            ae::ParamFlag::SUPERVISE,
+            ae::ParamUIFlags::empty(),
+        )?;
+
+        params.add(
+            Params::RandomSeed,
+            "Random Seed",
+            SliderDef::setup(|d| {
+                d.set_valid_min(0);
+                d.set_valid_max(10000);
+                d.set_slider_min(0);
+                d.set_slider_max(1000);
+                d.set_default(0);
+            }),
+        )?;
+
+        params.add_with_flags(
+            Params::AnimateRandom,
+            "Animate per Frame",
+            CheckBoxDef::setup(|d| {
+                d.set_default(false);
+            }),
+            ae::ParamFlag::SUPERVISE,
+            ae::ParamUIFlags::empty(),
+        )?;
+
+        params.add(
+            Params::OperandFit,
+            "Operand Fit",
+            PopupDef::setup(|d| {
+                d.set_options(&[
+                    "Clamp Edge",
+                    "Tile",
+                    "Mirror",
+                    "Stretch to Fit",
+                    "Center with Transparent",
+                ]);
+                d.set_default(1);
+            }),
+        )?;
+
+        params.add(
+            Params::OutsideA,
+            "Outside A",
+            PopupDef::setup(|d| {
+                d.set_options(&["Transparent", "Clamp Edge"]);
+                d.set_default(1);
+            }),
+        )?;
+
+        params.add(
+            Params::KeyColor,
+            "Key Color",
+            ColorDef::setup(|d| {
+                d.set_default(Pixel8 {
+                    red: 0,
+                    green: 255,
+                    blue: 0,
+                    alpha: 255,
+                });
+            }),
+        )?;
+
+        params.add(
+            Params::LumaStandard,
+            "Luminance Standard",
+            PopupDef::setup(|d| {
+                d.set_options(&["Rec.709", "Rec.601", "Rec.2020", "Average"]);
+                d.set_default(1);
+            }),
+        )?;
+
+        params.add_with_flags(
+            Params::KernelPreset,
+            "Kernel Preset",
+            PopupDef::setup(|d| {
+                d.set_options(&[
+                    "Custom", "Identity", "Sharpen", "Edge", "Emboss", "Box Blur",
+                ]);
+                d.set_default(1);
+            }),
+            ae::ParamFlag::SUPERVISE,
+            ae::ParamUIFlags::empty(),
+        )?;
+
+        for (i, id) in KERNEL_CELLS.into_iter().enumerate() {
+            params.add(
+                id,
+                &format!("K{}{}", i / 3, i % 3),
+                FloatSliderDef::setup(|d| {
+                    d.set_valid_min(-100.0);
+                    d.set_valid_max(100.0);
+                    d.set_slider_min(-10.0);
+                    d.set_slider_max(10.0);
+                    d.set_default(if i == 4 { 1.0 } else { 0.0 });
+                    d.set_precision(3);
+                }),
+            )?;
+        }
+
+        params.add(
+            Params::NormalizeKernel,
+            "Normalize Kernel",
+            CheckBoxDef::setup(|d| {
+                d.set_default(false);
+            }),
+        )?;
+
+        params.add(
+            Params::SpectralMixMode,
+            "Spectral Mix Mode",
+            PopupDef::setup(|d| {
+                d.set_options(&[
+                    "Low A / High B",
+                    "Low B / High A",
+                    "Cross-Fade A",
+                    "Cross-Fade B",
+                ]);
+                d.set_default(1);
+            }),
+        )?;
+
+        params.add(
+            Params::CrossoverSoftness,
+            "Crossover Softness",
+            FloatSliderDef::setup(|d| {
+                d.set_valid_min(0.0);
+                d.set_valid_max(1.0);
+                d.set_slider_min(0.0);
+                d.set_slider_max(1.0);
+                d.set_default(0.1);
+                d.set_precision(3);
+            }),
+        )?;
+
+        params.add_with_flags(
+            Params::FeedbackMode,
+            "Feedback",
+            CheckBoxDef::setup(|d| {
+                d.set_default(false);
+            }),
+            ae::ParamFlag::SUPERVISE,
+            ae::ParamUIFlags::empty(),
+        )?;
+
+        params.add(
+            Params::FeedbackDecay,
+            "Feedback Decay",
+            FloatSliderDef::setup(|d| {
+                d.set_valid_min(0.0);
+                d.set_valid_max(1.0);
+                d.set_slider_min(0.0);
+                d.set_slider_max(1.0);
+                d.set_default(0.5);
+                d.set_precision(3);
+            }),
+        )?;
+
+        params.add_group(
+            Params::PostAdjustGroupStart,
+            Params::PostAdjustGroupEnd,
+            "Post Adjust",
+            true,
+            |params| {
+                for (channel, (gain, offset)) in ["R", "G", "B", "A"].iter().zip(POST_ADJUST_PARAMS)
+                {
+                    params.add(
+                        gain,
+                        &format!("Gain {channel}"),
+                        FloatSliderDef::setup(|d| {
+                            d.set_valid_min(-100.0);
+                            d.set_valid_max(100.0);
+                            d.set_slider_min(0.0);
+                            d.set_slider_max(4.0);
+                            d.set_default(1.0);
+                            d.set_precision(3);
+                        }),
+                    )?;
+                    params.add(
+                        offset,
+                        &format!("Offset {channel}"),
+                        FloatSliderDef::setup(|d| {
+                            d.set_valid_min(-100.0);
+                            d.set_valid_max(100.0);
+                            d.set_slider_min(-1.0);
+                            d.set_slider_max(1.0);
+                            d.set_default(0.0);
+                            d.set_precision(3);
+                        }),
+                    )?;
+                }
+                Ok(())
+            },
+        )?;
+
+        params.add(
+            Params::ResultMix,
+            "Result Mix",
+            FloatSliderDef::setup(|d| {
+                d.set_valid_min(0.0);
+                d.set_valid_max(1.0);
+                d.set_slider_min(0.0);
+                d.set_slider_max(1.0);
+                d.set_default(1.0);
+                d.set_precision(3);
+            }),
+        )?;
+
+        params.add(
+            Params::MixSpace,
+            "Mix Space",
+            PopupDef::setup(|d| {
+                d.set_options(&["Linear RGB", "OKLab"]);
+                d.set_default(1);
+            }),
+        )?;
+
+        params.add(
+            Params::DetailRadius,
+            "Detail Radius (px)",
+            FloatSliderDef::setup(|d| {
+                d.set_valid_min(0.0);
+                d.set_valid_max(FREQ_MATCH_MAX_RADIUS);
+                d.set_slider_min(0.0);
+                d.set_slider_max(50.0);
+                d.set_default(4.0);
+                d.set_precision(1);
+            }),
         )?;
 
         Ok(())
@@ -254,15 +844,9 @@ impl AdobePluginGlobal for Plugin {
                     self.aegp_id = Some(plugin_id);
                 }
             }
-            ae::Command::Render {
-                in_layer,
-                out_layer,
-            } => {
-                self.do_render(in_data, in_layer, out_data, out_layer, params)?;
-            }
             ae::Command::SmartPreRender { mut extra } => {
                 let req = extra.output_request();
-                if let Ok(in_result) = extra.callbacks().checkout_layer(
+                let a_rect = if let Ok(in_result) = extra.callbacks().checkout_layer(
                     0,
                     0,
                     &req,
@@ -272,29 +856,85 @@ impl AdobePluginGlobal for Plugin {
                 ) {
                     let _ = extra.union_result_rect(in_result.result_rect.into());
                     let _ = extra.union_max_result_rect(in_result.max_result_rect.into());
+                    in_result.result_rect
                 } else {
                     return Err(Error::InterruptCancel);
+                };
+
+                // Operand layers are declared as dependencies so AE invalidates the cache
+                // when they animate. For Add, whose result outside A is just the operand,
+                // their rects also widen the output (unless A's alpha is kept) so a larger
+                // operand is not cropped to A.
+                let op = math_op_from_popup(params.get(Params::Operation)?.as_popup()?.value());
+                let widen = op == MathOp::Add
+                    && !params.get(Params::UseOriginalAlpha)?.as_checkbox()?.value();
+                let non_empty = |r: &ae::sys::PF_LRect| r.right > r.left && r.bottom > r.top;
+                let a_origin = (a_rect.left, a_rect.top);
+                let mut out_origin = non_empty(&a_rect).then_some(a_origin);
+                let mut operand_origins = [a_origin; 2];
+                let mut checked_out = false;
+                for (slot, (id, checkout_id, needed)) in
+                    operand_checkouts(params)?.into_iter().enumerate()
+                {
+                    let (true, Some(index)) = (needed, params.index(id)) else {
+                        continue;
+                    };
+                    let Ok(result) = extra.callbacks().checkout_layer(
+                        index as i32,
+                        checkout_id as i32,
+                        &req,
+                        in_data.current_time(),
+                        in_data.time_step(),
+                        in_data.time_scale(),
+                    ) else {
+                        continue;
+                    };
+                    let rect = result.result_rect;
+                    operand_origins[slot] = (rect.left, rect.top);
+                    checked_out = true;
+                    if widen && non_empty(&rect) {
+                        let _ = extra.union_result_rect(rect.into());
+                        let _ = extra.union_max_result_rect(result.max_result_rect.into());
+                        out_origin = Some(out_origin.map_or((rect.left, rect.top), |(x, y)| {
+                            (x.min(rect.left), y.min(rect.top))
+                        }));
+                    }
                 }
-            }
-            ae::Command::SmartRender { extra } => {
-                let cb = extra.callbacks();
-                let in_layer_opt = cb.checkout_layer_pixels(0)?;
-                let out_layer_opt = cb.checkout_output()?;
-
-                if let (Some(in_layer), Some(out_layer)) = (in_layer_opt, out_layer_opt) {
-                    self.do_render(in_data, in_layer, out_data, out_layer, params)?;
+                // Operand buffers start at their own result rects, which need not be A's.
+                if checked_out {
+                    extra.set_pre_render_data(Extent {
+                        out: out_origin.unwrap_or(a_origin),
+                        a: a_origin,
+                        operands: operand_origins,
+                    });
                 }
-
-                cb.checkin_layer_pixels(0)?;
             }
             ae::Command::UserChangedParam { param_index } => {
                 let t = params.type_at(param_index);
-                if t == Params::Operation || t == Params::InputBSource || t == Params::InputCSource
+                if t == Params::Operation
+                    || t == Params::InputBSource
+                    || t == Params::InputCSource
+                    || t == Params::UseOriginalAlpha
+                {
+                    out_data.set_out_flag(OutFlags::RefreshUi, true);
+                }
+                if t == Params::Operation
+                    || t == Params::InputBSource
+                    || t == Params::AnimateRandom
+                    || t == Params::FeedbackMode
                 {
+                    out_data.set_out_flag(OutFlags::NonParamVary, varies_per_frame(params)?);
+                }
+                if t == Params::FeedbackMode {
                     out_data.set_out_flag(OutFlags::RefreshUi, true);
+                    out_data.set_out_flag(OutFlags::ForceRerender, true);
+                }
+                if t == Params::KernelPreset {
+                    Self::apply_kernel_preset(params)?;
                 }
             }
             ae::Command::UpdateParamsUi => {
+                out_data.set_out_flag(OutFlags::NonParamVary, varies_per_frame(params)?);
                 let mut params_copy = params.cloned();
                 self.update_params_ui(in_data, &mut params_copy)?;
             }
@@ -355,6 +995,9 @@ impl Plugin {
             Params::ValueB,
             uses_b && matches!(source_b, InputSource::Value),
         )?;
+        let uses_random = uses_b && matches!(source_b, InputSource::Random);
+        self.set_param_visible(in_data, params, Params::RandomSeed, uses_random)?;
+        self.set_param_visible(in_data, params, Params::AnimateRandom, uses_random)?;
 
         self.set_param_visible(in_data, params, Params::InputCSource, uses_c)?;
         self.set_param_visible(in_data, params, Params::LayerC, uses_c)?;
@@ -370,8 +1013,56 @@ impl Plugin {
             Params::ValueC,
             uses_c && matches!(source_c, InputSource::Value),
         )?;
+        let uses_operand_layer = (uses_b && matches!(source_b, InputSource::Layer))
+            || (uses_c && matches!(source_c, InputSource::Layer));
+        Self::set_param_enabled(params, Params::OperandFit, uses_operand_layer)?;
+        // The output only grows past A when A's alpha is not kept.
+        let use_original_alpha = params.get(Params::UseOriginalAlpha)?.as_checkbox()?.value();
+        Self::set_param_enabled(
+            params,
+            Params::OutsideA,
+            uses_operand_layer && !use_original_alpha,
+        )?;
+        self.set_param_visible(in_data, params, Params::KeyColor, op == MathOp::ChromaKey)?;
+        Self::set_param_enabled(params, Params::LumaStandard, operation_uses_luma(op))?;
         Self::set_param_enabled(params, Params::Epsilon, uses_eps)?;
+        let feedback = params.get(Params::FeedbackMode)?.as_checkbox()?.value();
+        Self::set_param_enabled(params, Params::FeedbackDecay, feedback)?;
+        // Use Original Alpha overrides the output alpha, so its pair would do nothing.
+        self.set_param_visible(in_data, params, Params::GainA, !use_original_alpha)?;
+        self.set_param_visible(in_data, params, Params::OffsetA, !use_original_alpha)?;
+
+        let uses_kernel = op == MathOp::ConvMatrix;
+        self.set_param_visible(in_data, params, Params::KernelPreset, uses_kernel)?;
+        for id in KERNEL_CELLS {
+            self.set_param_visible(in_data, params, id, uses_kernel)?;
+        }
+        self.set_param_visible(in_data, params, Params::NormalizeKernel, uses_kernel)?;
 
+        let spectral_mix = op == MathOp::SpectralMix;
+        self.set_param_visible(in_data, params, Params::SpectralMixMode, spectral_mix)?;
+        self.set_param_visible(in_data, params, Params::CrossoverSoftness, spectral_mix)?;
+        self.set_param_visible(
+            in_data,
+            params,
+            Params::DetailRadius,
+            op == MathOp::FreqMatch,
+        )?;
+
+        Ok(())
+    }
+
+    // Writes the selected preset into the kernel cells; "Custom" leaves them as is.
+    fn apply_kernel_preset(params: &mut ae::Parameters<Params>) -> Result<(), Error> {
+        let preset = params.get(Params::KernelPreset)?.as_popup()?.value();
+        let Some(kernel) = kernel_preset(preset) else {
+            return Ok(());
+        };
+        for (id, v) in KERNEL_CELLS.into_iter().zip(kernel.iter().flatten()) {
+            let mut p = params.get_mut(id)?;
+            p.as_float_slider_mut()?.set_value(*v as f64);
+            p.set_value_changed();
+        }
         Ok(())
     }
 
@@ -441,106 +1132,314 @@ impl Plugin {
 
     fn do_render(
         &self,
-        _in_data: InData,
+        in_data: InData,
         in_layer: Layer,
-        _out_data: OutData,
         mut out_layer: Layer,
         params: &mut Parameters<Params>,
+        smart: Option<([Option<Layer>; 2], Option<Extent>)>,
+        instance: &Instance,
     ) -> Result<(), Error> {
-        let w = in_layer.width();
-        let h = in_layer.height();
+        let feedback = &instance.feedback;
+        let (smart_operands, extent) = match smart {
+            Some((layers, extent)) => (Some(layers), extent),
+            None => (None, None),
+        };
+        // A widened SmartRender output no longer matches A, so the output grid is
+        // used and A is placed on it by its layer-space offset. Without widening
+        // the two grids coincide.
+        let (w, h) = match extent {
+            Some(_) => (out_layer.width(), out_layer.height()),
+            None => (in_layer.width(), in_layer.height()),
+        };
         if w == 0 || h == 0 {
             return Ok(());
         }
 
-        let op = math_op_from_popup(params.get(Params::Operation)?.as_popup()?.value());
+        let op = math_op_from_popup(instance.popup(params, Params::Operation)?);
         let uses_b = operation_uses_b(op);
         let uses_c = operation_uses_c(op);
-        let input_b_source =
-            input_source_from_popup(params.get(Params::InputBSource)?.as_popup()?.value());
-        let input_c_source =
-            input_source_from_popup(params.get(Params::InputCSource)?.as_popup()?.value());
+        let input_b_source = input_source_from_popup(instance.popup(params, Params::InputBSource)?);
+        let input_c_source = input_source_from_popup(instance.popup(params, Params::InputCSource)?);
         let value_b = params.get(Params::ValueB)?.as_float_slider()?.value() as f32;
         let value_c = params.get(Params::ValueC)?.as_float_slider()?.value() as f32;
-        let epsilon = params.get(Params::Epsilon)?.as_float_slider()?.value() as f32;
-        let epsilon = epsilon.max(1.0e-12);
+        let epsilon =
+            effective_epsilon(params.get(Params::Epsilon)?.as_float_slider()?.value() as f32);
         let clamp_result = params.get(Params::ClampResult)?.as_checkbox()?.value();
+        let result_mix = params.get(Params::ResultMix)?.as_float_slider()?.value() as f32;
+        let result_mix = result_mix.clamp(0.0, 1.0);
+        let mix_space = mix_space_from_popup(params.get(Params::MixSpace)?.as_popup()?.value());
         let use_original_alpha = params.get(Params::UseOriginalAlpha)?.as_checkbox()?.value();
+        let post_adjust = PostAdjust::new(params)?;
+        let feedback_mode = params.get(Params::FeedbackMode)?.as_checkbox()?.value();
+        let feedback_decay = params
+            .get(Params::FeedbackDecay)?
+            .as_float_slider()?
+            .value() as f32;
+        let feedback_decay = feedback_decay.clamp(0.0, 1.0);
+        // Only Feedback mode touches the history, so other renders never wait on
+        // it. Without the previous frame at this size the loop starts from A alone.
+        let frame_time = (
+            in_data.current_time(),
+            in_data.time_step(),
+            in_data.time_scale(),
+        );
+        let prev_output = if feedback_mode {
+            lock_feedback(feedback).previous(frame_time, w, h)
+        } else {
+            None
+        };
+        let prev_output = prev_output.as_deref();
+        let operand_fit =
+            operand_fit_from_popup(params.get(Params::OperandFit)?.as_popup()?.value());
+        let key_color = params.get(Params::KeyColor)?.as_color()?.float_value()?;
+        let key_lab = to_oklab(key_color);
+        let luma_weights = luma_weights(luma_standard_from_popup(
+            params.get(Params::LumaStandard)?.as_popup()?.value(),
+        ));
+
+        // Operand layers are decoded once up front instead of per pixel. SmartRender
+        // hands them in already checked out at the current time.
+        let [smart_b, smart_c] = match smart_operands {
+            Some(layers) => layers.map(Some),
+            None => [None, None],
+        };
+        let layer_b_checkout;
+        let layer_b = match smart_b {
+            Some(layer) => layer,
+            None => {
+                layer_b_checkout = params.checkout_at(Params::LayerB, None, None, None)?;
+                layer_b_checkout.as_layer()?.value()
+            }
+        };
+        let use_layer_b = uses_b && matches!(input_b_source, InputSource::Layer);
+        let operand_b = if uses_b && matches!(input_b_source, InputSource::Random) {
+            let mut seed = params.get(Params::RandomSeed)?.as_slider()?.value() as u32;
+            if params.get(Params::AnimateRandom)?.as_checkbox()?.value() {
+                seed ^= hash_u32(in_data.current_time() as u32);
+            }
+            Operand::Random { seed }
+        } else {
+            let origin = extent.map_or((0, 0), |e| e.operand_offset(0));
+            Operand::new(
+                use_layer_b,
+                layer_b.as_ref(),
+                value_b,
+                operand_fit,
+                (w, h),
+                origin,
+            )
+        };
+
+        let layer_c_checkout;
+        let layer_c = match smart_c {
+            Some(layer) => layer,
+            None => {
+                layer_c_checkout = params.checkout_at(Params::LayerC, None, None, None)?;
+                layer_c_checkout.as_layer()?.value()
+            }
+        };
+        let use_layer_c = uses_c && matches!(input_c_source, InputSource::Layer);
+        let origin_c = extent.map_or((0, 0), |e| e.operand_offset(1));
+        let operand_c = Operand::new(
+            use_layer_c,
+            layer_c.as_ref(),
+            value_c,
+            operand_fit,
+            (w, h),
+            origin_c,
+        );
 
-        let layer_b_checkout = params.checkout_at(Params::LayerB, None, None, None)?;
-        let layer_b = layer_b_checkout.as_layer()?.value();
-        let layer_b_world_type = layer_b.as_ref().map(|layer| layer.world_type());
-        let use_layer_b =
-            uses_b && matches!(input_b_source, InputSource::Layer) && layer_b.is_some();
-
-        let layer_c_checkout = params.checkout_at(Params::LayerC, None, None, None)?;
-        let layer_c = layer_c_checkout.as_layer()?.value();
-        let layer_c_world_type = layer_c.as_ref().map(|layer| layer.world_type());
-        let use_layer_c =
-            uses_c && matches!(input_c_source, InputSource::Layer) && layer_c.is_some();
-
-        let in_world_type = in_layer.world_type();
+        let outside_a = outside_a_from_popup(params.get(Params::OutsideA)?.as_popup()?.value());
+        let a_offset = extent.map_or((0, 0), |e| e.a_offset());
+        let a_pixels = decode_a(&in_layer, (w, h), a_offset, outside_a);
         let out_world_type = out_layer.world_type();
         let out_is_f32 = matches!(
             out_world_type,
             ae::aegp::WorldType::F32 | ae::aegp::WorldType::None
         );
 
+        let correlation = if op == MathOp::CrossCorrelation {
+            let mut luma_a = vec![0.0f32; w * h];
+            let mut luma_b = vec![0.0f32; w * h];
+            for y in 0..h {
+                for x in 0..w {
+                    let idx = y * w + x;
+                    luma_a[idx] = luma(a_pixels[idx], luma_weights);
+                    luma_b[idx] = luma(operand_b.sample(x, y), luma_weights);
+                }
+            }
+            Some(PatchStats::new(&luma_a, &luma_b, w, h))
+        } else {
+            None
+        };
+
+        let convolved = if op == MathOp::ConvMatrix {
+            let mut kernel = [[0.0f32; 3]; 3];
+            for (i, id) in KERNEL_CELLS.into_iter().enumerate() {
+                kernel[i / 3][i % 3] = params.get(id)?.as_float_slider()?.value() as f32;
+            }
+            let normalize = params.get(Params::NormalizeKernel)?.as_checkbox()?.value();
+            Some(convolve_layer(&a_pixels, w, h, &kernel, normalize))
+        } else {
+            None
+        };
+
+        let derivative = match op {
+            MathOp::GradientX
+            | MathOp::GradientY
+            | MathOp::GradientMagnitude
+            | MathOp::Laplacian => Some(derivative_layer(&a_pixels, w, h, op)),
+            _ => None,
+        };
+
+        let freq_convolved = if op == MathOp::FreqConvolve {
+            // Only a Layer source is a kernel; Render checks Layer B out regardless.
+            let kernel_layer = layer_b.as_ref().filter(|_| use_layer_b);
+            let kernel = FreqKernel::new(kernel_layer, value_b, luma_weights);
+            Some(frequency_convolve(&a_pixels, w, h, &kernel))
+        } else {
+            None
+        };
+
+        let luma_cdf = if op == MathOp::HistogramEqualize {
+            Some(LumaCdf::new(&a_pixels, w, h, luma_weights))
+        } else {
+            None
+        };
+
+        let freq_match = if op == MathOp::FreqMatch {
+            // C is the per-pixel detail mix, so the split has a radius of its own.
+            let radius = params.get(Params::DetailRadius)?.as_float_slider()?.value() as f32;
+            let sigma = radius.clamp(0.0, FREQ_MATCH_MAX_RADIUS);
+            Some(frequency_match(&a_pixels, &operand_b, w, h, sigma))
+        } else {
+            None
+        };
+
+        let spectral_mixed = if op == MathOp::SpectralMix {
+            let mode = spectral_mix_mode_from_popup(
+                params.get(Params::SpectralMixMode)?.as_popup()?.value(),
+            );
+            let softness = params
+                .get(Params::CrossoverSoftness)?
+                .as_float_slider()?
+                .value() as f32;
+            let crossover = operand_c.mean_luma(w, h, luma_weights).clamp(0.0, 1.0);
+            let band = (crossover, softness.clamp(0.0, 1.0));
+            Some(spectral_mix(&a_pixels, &operand_b, (w, h), band, mode))
+        } else {
+            None
+        };
+
+        let style_matched = if op == MathOp::StyleMatch {
+            Some(style_match(&a_pixels, &operand_b, (w, h)))
+        } else {
+            None
+        };
+
         let progress_final = h as i32;
         out_layer.iterate(0, progress_final, None, |x, y, mut dst| {
             let x = x as usize;
             let y = y as usize;
 
-            let src_a = read_pixel_f32(&in_layer, in_world_type, x, y);
+            // Non-finite inputs (e.g. NaN from an upstream effect) are zeroed up front so
+            // they cannot reach apply_math or the original alpha path.
+            let input_a = finite_pixel(a_pixels[y * w + x]);
+            let mut src_a = input_a;
+            if let Some(prev) = prev_output {
+                let i = (y * w + x) * 4;
+                let mix = |a: f32, p: f32| a * (1.0 - feedback_decay) + p * feedback_decay;
+                src_a = finite_pixel(PixelF32 {
+                    red: mix(src_a.red, prev[i]),
+                    green: mix(src_a.green, prev[i + 1]),
+                    blue: mix(src_a.blue, prev[i + 2]),
+                    alpha: mix(src_a.alpha, prev[i + 3]),
+                });
+            }
 
-            let src_b = sample_input(
-                x,
-                y,
-                use_layer_b,
-                layer_b.as_ref(),
-                layer_b_world_type,
-                value_b,
-            );
-            let src_c = sample_input(
-                x,
-                y,
-                use_layer_c,
-                layer_c.as_ref(),
-                layer_c_world_type,
-                value_c,
-            );
+            let src_b = finite_pixel(operand_b.sample(x, y));
+            let src_c = finite_pixel(operand_c.sample(x, y));
 
             let clamp_01 = clamp_result || !out_is_f32;
 
-            let mut out_px = PixelF32 {
-                red: sanitize_output(
-                    apply_math(op, src_a.red, src_b.red, src_c.red, epsilon),
-                    clamp_01,
-                ),
-                green: sanitize_output(
-                    apply_math(op, src_a.green, src_b.green, src_c.green, epsilon),
-                    clamp_01,
-                ),
-                blue: sanitize_output(
-                    apply_math(op, src_a.blue, src_b.blue, src_c.blue, epsilon),
-                    clamp_01,
-                ),
-                alpha: sanitize_output(
-                    apply_math(op, src_a.alpha, src_b.alpha, src_c.alpha, epsilon),
-                    clamp_01,
-                ),
+            let raw_px = match op {
+                MathOp::ChromaKey => {
+                    let matte = chroma_key_matte(
+                        src_a,
+                        key_lab,
+                        luma(src_b, luma_weights),
+                        luma(src_c, luma_weights),
+                    );
+                    fill_pixel(matte)
+                }
+                MathOp::CrossCorrelation => {
+                    let radius = luma(src_c, luma_weights).round().clamp(1.0, 16.0) as usize;
+                    let r = correlation
+                        .as_ref()
+                        .map_or(0.0, |stats| patch_correlation(stats, x, y, radius));
+                    gray_pixel(r * 0.5 + 0.5)
+                }
+                MathOp::HistogramEqualize => {
+                    let old_luma = luma(src_a, luma_weights);
+                    let new_luma = luma_cdf.as_ref().map_or(old_luma, |cdf| cdf.map(old_luma));
+                    let scale = if old_luma.abs() > epsilon {
+                        new_luma / old_luma
+                    } else {
+                        1.0
+                    };
+                    let eq = |a: f32, strength: f32| a + (a * scale - a) * strength;
+                    PixelF32 {
+                        red: eq(src_a.red, src_b.red),
+                        green: eq(src_a.green, src_b.green),
+                        blue: eq(src_a.blue, src_b.blue),
+                        alpha: src_a.alpha,
+                    }
+                }
+                MathOp::FreqMatch => freq_match.as_ref().map_or(src_a, |bands| {
+                    let (low, high) = (bands.low_a[y * w + x], bands.high_b[y * w + x]);
+                    PixelF32 {
+                        red: low.red + high.red * src_c.red,
+                        green: low.green + high.green * src_c.green,
+                        blue: low.blue + high.blue * src_c.blue,
+                        alpha: src_a.alpha,
+                    }
+                }),
+                MathOp::ConvMatrix
+                | MathOp::FreqConvolve
+                | MathOp::SpectralMix
+                | MathOp::StyleMatch => convolved
+                    .as_ref()
+                    .or(freq_convolved.as_ref())
+                    .or(spectral_mixed.as_ref())
+                    .or(style_matched.as_ref())
+                    .map_or(src_a, |buf| buf[y * w + x]),
+                MathOp::GradientX
+                | MathOp::GradientY
+                | MathOp::GradientMagnitude
+                | MathOp::Laplacian => {
+                    let d = derivative.as_ref().map_or(src_a, |buf| buf[y * w + x]);
+                    PixelF32 {
+                        red: d.red * src_b.red,
+                        green: d.green * src_b.green,
+                        blue: d.blue * src_b.blue,
+                        alpha: src_a.alpha,
+                    }
+                }
+                _ => PixelF32 {
+                    red: apply_math(op, src_a.red, src_b.red, src_c.red, epsilon),
+                    green: apply_math(op, src_a.green, src_b.green, src_c.green, epsilon),
+                    blue: apply_math(op, src_a.blue, src_b.blue, src_c.blue, epsilon),
+                    alpha: apply_math(op, src_a.alpha, src_b.alpha, src_c.alpha, epsilon),
+                },
             };
+            let mut out_px = post_adjust.apply(raw_px, clamp_01);
+            if result_mix < 1.0 {
+                out_px = mix_result(input_a, out_px, result_mix, mix_space, clamp_01);
+            }
 
+            // A's own alpha, not the feedback-mixed one, so the checkbox keeps its meaning.
             if use_original_alpha {
-                let mut out_alpha = src_a.alpha;
-                if !out_alpha.is_finite() {
-                    out_alpha = 0.0;
-                }
-                out_alpha = out_alpha.clamp(0.0, 1.0);
-                out_px.red *= out_alpha;
-                out_px.green *= out_alpha;
-                out_px.blue *= out_alpha;
-                out_px.alpha = out_alpha;
+                out_px = apply_original_alpha(out_px, input_a.alpha);
             }
 
             match out_world_type {
@@ -554,17 +1453,107 @@ impl Plugin {
             Ok(())
         })?;
 
+        if feedback_mode {
+            let mut data = Vec::with_capacity(w * h * 4);
+            for y in 0..h {
+                for x in 0..w {
+                    let px = read_pixel_f32(&out_layer, out_world_type, x, y);
+                    data.extend_from_slice(&[px.red, px.green, px.blue, px.alpha]);
+                }
+            }
+            lock_feedback(feedback).store(frame_time, w, h, data);
+        }
+
         Ok(())
     }
 }
 
+const LAYER_B_CHECKOUT_ID: u32 = 1;
+const LAYER_C_CHECKOUT_ID: u32 = 2;
+
+// (layer param, checkout id, whether the current operation reads that layer).
+fn operand_checkouts(params: &Parameters<Params>) -> Result<[(Params, u32, bool); 2], Error> {
+    let op = math_op_from_popup(params.get(Params::Operation)?.as_popup()?.value());
+    let source_b = input_source_from_popup(params.get(Params::InputBSource)?.as_popup()?.value());
+    let source_c = input_source_from_popup(params.get(Params::InputCSource)?.as_popup()?.value());
+    Ok([
+        (
+            Params::LayerB,
+            LAYER_B_CHECKOUT_ID,
+            operation_uses_b(op) && matches!(source_b, InputSource::Layer),
+        ),
+        (
+            Params::LayerC,
+            LAYER_C_CHECKOUT_ID,
+            operation_uses_c(op) && matches!(source_c, InputSource::Layer),
+        ),
+    ])
+}
+
+// An animated Random operand and Feedback change the output without any param
+// or layer changing.
+fn varies_per_frame(params: &Parameters<Params>) -> Result<bool, Error> {
+    if params.get(Params::FeedbackMode)?.as_checkbox()?.value() {
+        return Ok(true);
+    }
+    let op = math_op_from_popup(params.get(Params::Operation)?.as_popup()?.value());
+    let source_b = input_source_from_popup(params.get(Params::InputBSource)?.as_popup()?.value());
+    Ok(operation_uses_b(op)
+        && matches!(source_b, InputSource::Random)
+        && params.get(Params::AnimateRandom)?.as_checkbox()?.value())
+}
+
 fn input_source_from_popup(value: i32) -> InputSource {
     match value {
         2 => InputSource::Layer,
+        3 => InputSource::Random,
         _ => InputSource::Value,
     }
 }
 
+fn operand_fit_from_popup(value: i32) -> OperandFit {
+    match value {
+        2 => OperandFit::Tile,
+        3 => OperandFit::Mirror,
+        4 => OperandFit::Stretch,
+        5 => OperandFit::Center,
+        _ => OperandFit::ClampEdge,
+    }
+}
+
+fn outside_a_from_popup(value: i32) -> OutsideA {
+    match value {
+        2 => OutsideA::ClampEdge,
+        _ => OutsideA::Transparent,
+    }
+}
+
+fn mix_space_from_popup(value: i32) -> MixSpace {
+    match value {
+        2 => MixSpace::Oklab,
+        _ => MixSpace::Linear,
+    }
+}
+
+fn luma_standard_from_popup(value: i32) -> LumaStandard {
+    match value {
+        2 => LumaStandard::Rec601,
+        3 => LumaStandard::Rec2020,
+        4 => LumaStandard::Average,
+        _ => LumaStandard::Rec709,
+    }
+}
+
+fn luma_weights(standard: LumaStandard) -> [f32; 3] {
+    match standard {
+        LumaStandard::Rec709 => [0.2126, 0.7152, 0.0722],
+        LumaStandard::Rec601 => [0.299, 0.587, 0.114],
+        LumaStandard::Rec2020 => [0.2627, 0.6780, 0.0593],
+        LumaStandard::Average => [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0],
+    }
+}
+
+// Indices are part of the saved project format; see params_setup.
 fn math_op_from_popup(value: i32) -> MathOp {
     match value {
         2 => MathOp::Subtract,
@@ -605,10 +1594,34 @@ fn math_op_from_popup(value: i32) -> MathOp {
         37 => MathOp::HyperbolicTangent,
         38 => MathOp::ToRadians,
         39 => MathOp::ToDegrees,
+        40 => MathOp::ChromaKey,
+        41 => MathOp::Mix,
+        42 => MathOp::Select,
+        43 => MathOp::ClampRange,
+        44 => MathOp::CrossCorrelation,
+        45 => MathOp::FreqMatch,
+        46 => MathOp::ConvMatrix,
+        47 => MathOp::HistogramEqualize,
+        48 => MathOp::GradientX,
+        49 => MathOp::GradientY,
+        50 => MathOp::GradientMagnitude,
+        51 => MathOp::Laplacian,
+        52 => MathOp::FreqConvolve,
+        53 => MathOp::SpectralMix,
+        54 => MathOp::StyleMatch,
         _ => MathOp::Add,
     }
 }
 
+fn spectral_mix_mode_from_popup(value: i32) -> SpectralMixMode {
+    match value {
+        2 => SpectralMixMode::LowBHighA,
+        3 => SpectralMixMode::CrossFadeA,
+        4 => SpectralMixMode::CrossFadeB,
+        _ => SpectralMixMode::LowAHighB,
+    }
+}
+
 fn operation_ui_info(op: MathOp) -> OperationUiInfo {
     match op {
         MathOp::Add => OperationUiInfo {
@@ -806,6 +1819,81 @@ fn operation_ui_info(op: MathOp) -> OperationUiInfo {
             b_label: "Operand",
             c_label: "Parameter",
         },
+        MathOp::ChromaKey => OperationUiInfo {
+            expression: "1-smoothstep(B-C,B+C,|A-Key|)",
+            b_label: "Similarity",
+            c_label: "Smoothness",
+        },
+        MathOp::Mix => OperationUiInfo {
+            expression: "lerp(A,B,C)",
+            b_label: "Other Value",
+            c_label: "Mix Factor",
+        },
+        MathOp::Select => OperationUiInfo {
+            expression: "C>0.5 ? B:A",
+            b_label: "If True",
+            c_label: "Threshold",
+        },
+        MathOp::ClampRange => OperationUiInfo {
+            expression: "clamp(A,B,C)",
+            b_label: "Min",
+            c_label: "Max",
+        },
+        MathOp::CrossCorrelation => OperationUiInfo {
+            expression: "ncc(A,B,radius C)",
+            b_label: "Compare To",
+            c_label: "Patch Radius",
+        },
+        MathOp::FreqMatch => OperationUiInfo {
+            expression: "low(A)+high(B)*C",
+            b_label: "Detail Source",
+            c_label: "Detail Mix",
+        },
+        MathOp::ConvMatrix => OperationUiInfo {
+            expression: "A*K",
+            b_label: "Unused",
+            c_label: "Unused",
+        },
+        MathOp::HistogramEqualize => OperationUiInfo {
+            expression: "lerp(A,eq(A),B)",
+            b_label: "Strength",
+            c_label: "Unused",
+        },
+        MathOp::GradientX => OperationUiInfo {
+            expression: "dA/dx*B",
+            b_label: "Scale",
+            c_label: "Unused",
+        },
+        MathOp::GradientY => OperationUiInfo {
+            expression: "dA/dy*B",
+            b_label: "Scale",
+            c_label: "Unused",
+        },
+        MathOp::GradientMagnitude => OperationUiInfo {
+            expression: "|grad A|*B",
+            b_label: "Scale",
+            c_label: "Unused",
+        },
+        MathOp::Laplacian => OperationUiInfo {
+            expression: "lap(A)*B",
+            b_label: "Scale",
+            c_label: "Unused",
+        },
+        MathOp::FreqConvolve => OperationUiInfo {
+            expression: "A*B (FFT)",
+            b_label: "Kernel",
+            c_label: "Unused",
+        },
+        MathOp::SpectralMix => OperationUiInfo {
+            expression: "low(A)+high(B) (FFT)",
+            b_label: "Second Layer",
+            c_label: "Crossover",
+        },
+        MathOp::StyleMatch => OperationUiInfo {
+            expression: "A*rms(B)/rms(A) per octave",
+            b_label: "Style Source",
+            c_label: "Unused",
+        },
     }
 }
 
@@ -833,13 +1921,36 @@ fn operation_uses_b(op: MathOp) -> bool {
             | MathOp::HyperbolicTangent
             | MathOp::ToRadians
             | MathOp::ToDegrees
+            | MathOp::ConvMatrix
     )
 }
 
 fn operation_uses_c(op: MathOp) -> bool {
     matches!(
         op,
-        MathOp::Compare | MathOp::SmoothMinimum | MathOp::SmoothMaximum | MathOp::Wrap
+        MathOp::Compare
+            | MathOp::SmoothMinimum
+            | MathOp::SmoothMaximum
+            | MathOp::Wrap
+            | MathOp::ChromaKey
+            | MathOp::Mix
+            | MathOp::Select
+            | MathOp::ClampRange
+            | MathOp::CrossCorrelation
+            | MathOp::FreqMatch
+            | MathOp::SpectralMix
+    )
+}
+
+// Operations that reduce layer inputs to a single luminance value.
+fn operation_uses_luma(op: MathOp) -> bool {
+    matches!(
+        op,
+        MathOp::ChromaKey
+            | MathOp::CrossCorrelation
+            | MathOp::HistogramEqualize
+            | MathOp::FreqConvolve
+            | MathOp::SpectralMix
     )
 }
 
@@ -855,6 +1966,7 @@ fn operation_uses_epsilon(op: MathOp) -> bool {
             | MathOp::Wrap
             | MathOp::Snap
             | MathOp::PingPong
+            | MathOp::HistogramEqualize
     )
 }
 
@@ -937,6 +2049,28 @@ fn apply_math(op: MathOp, a: f32, b: f32, c: f32, eps: f32) -> f32 {
         MathOp::HyperbolicTangent => a.tanh(),
         MathOp::ToRadians => a.to_radians(),
         MathOp::ToDegrees => a.to_degrees(),
+        // Evaluated on whole pixels in do_render.
+        MathOp::ChromaKey
+        | MathOp::CrossCorrelation
+        | MathOp::FreqMatch
+        | MathOp::ConvMatrix
+        | MathOp::HistogramEqualize
+        | MathOp::GradientX
+        | MathOp::GradientY
+        | MathOp::GradientMagnitude
+        | MathOp::Laplacian
+        | MathOp::FreqConvolve
+        | MathOp::SpectralMix
+        | MathOp::StyleMatch => a,
+        MathOp::Mix => a + (b - a) * c,
+        MathOp::Select => {
+            if c > 0.5 {
+                b
+            } else {
+                a
+            }
+        }
+        MathOp::ClampRange => a.max(b.min(c)).min(b.max(c)),
     }
 }
 
@@ -1004,6 +2138,176 @@ fn ping_pong(v: f32, scale: f32, eps: f32) -> f32 {
     }
 }
 
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn to_oklab(px: PixelF32) -> Oklab<f32> {
+    Oklab::from_color(Srgb::new(px.red, px.green, px.blue).into_linear())
+}
+
+// Blends from A (t = 0) to the result (t = 1). OKLab keeps complementary
+// midpoints from going grey; alpha always mixes linearly.
+fn mix_result(a: PixelF32, result: PixelF32, t: f32, space: MixSpace, clamp_01: bool) -> PixelF32 {
+    let lerp = |from: f32, to: f32| from + (to - from) * t;
+    let alpha = sanitize_output(lerp(a.alpha, result.alpha), clamp_01);
+    match space {
+        MixSpace::Linear => PixelF32 {
+            red: sanitize_output(lerp(a.red, result.red), clamp_01),
+            green: sanitize_output(lerp(a.green, result.green), clamp_01),
+            blue: sanitize_output(lerp(a.blue, result.blue), clamp_01),
+            alpha,
+        },
+        MixSpace::Oklab => {
+            let (from, to) = (to_oklab(a), to_oklab(result));
+            let lab = Oklab::new(lerp(from.l, to.l), lerp(from.a, to.a), lerp(from.b, to.b));
+            let rgb = Srgb::from_linear(LinSrgb::from_color(lab));
+            PixelF32 {
+                red: sanitize_output(rgb.red, clamp_01),
+                green: sanitize_output(rgb.green, clamp_01),
+                blue: sanitize_output(rgb.blue, clamp_01),
+                alpha,
+            }
+        }
+    }
+}
+
+fn chroma_key_matte(px: PixelF32, key: Oklab<f32>, similarity: f32, smoothness: f32) -> f32 {
+    let lab = to_oklab(px);
+    let dl = lab.l - key.l;
+    let da = lab.a - key.a;
+    let db = lab.b - key.b;
+    let distance = (dl * dl + da * da + db * db).sqrt();
+    let smoothness = smoothness.abs();
+    1.0 - smoothstep(similarity - smoothness, similarity + smoothness, distance)
+}
+
+// Luminance with the selected standard, used wherever an operation needs one scalar per pixel.
+fn luma(px: PixelF32, weights: [f32; 3]) -> f32 {
+    weights[0] * px.red + weights[1] * px.green + weights[2] * px.blue
+}
+
+// Summed-area tables of A, B and their products, so every patch is O(1).
+struct PatchStats {
+    w: usize,
+    h: usize,
+    sum_a: Vec<f64>,
+    sum_b: Vec<f64>,
+    sum_aa: Vec<f64>,
+    sum_bb: Vec<f64>,
+    sum_ab: Vec<f64>,
+}
+
+impl PatchStats {
+    fn new(a: &[f32], b: &[f32], w: usize, h: usize) -> Self {
+        let stride = w + 1;
+        let len = stride * (h + 1);
+        let mut stats = Self {
+            w,
+            h,
+            sum_a: vec![0.0; len],
+            sum_b: vec![0.0; len],
+            sum_aa: vec![0.0; len],
+            sum_bb: vec![0.0; len],
+            sum_ab: vec![0.0; len],
+        };
+        for y in 0..h {
+            let mut row = [0.0f64; 5];
+            for x in 0..w {
+                let va = finite_or_zero(a[y * w + x]) as f64;
+                let vb = finite_or_zero(b[y * w + x]) as f64;
+                row[0] += va;
+                row[1] += vb;
+                row[2] += va * va;
+                row[3] += vb * vb;
+                row[4] += va * vb;
+                let above = y * stride + x + 1;
+                let idx = (y + 1) * stride + x + 1;
+                stats.sum_a[idx] = stats.sum_a[above] + row[0];
+                stats.sum_b[idx] = stats.sum_b[above] + row[1];
+                stats.sum_aa[idx] = stats.sum_aa[above] + row[2];
+                stats.sum_bb[idx] = stats.sum_bb[above] + row[3];
+                stats.sum_ab[idx] = stats.sum_ab[above] + row[4];
+            }
+        }
+        stats
+    }
+
+    fn rect_sum(&self, table: &[f64], x0: usize, y0: usize, x1: usize, y1: usize) -> f64 {
+        let stride = self.w + 1;
+        table[y1 * stride + x1] - table[y0 * stride + x1] - table[y1 * stride + x0]
+            + table[y0 * stride + x0]
+    }
+}
+
+// Pearson correlation of the A and B patches centred on (x, y), clipped to the frame.
+fn patch_correlation(stats: &PatchStats, x: usize, y: usize, radius: usize) -> f32 {
+    if stats.w == 0 || stats.h == 0 {
+        return 0.0;
+    }
+    let x = x.min(stats.w - 1);
+    let y = y.min(stats.h - 1);
+    let x0 = x.saturating_sub(radius);
+    let y0 = y.saturating_sub(radius);
+    let x1 = (x + radius + 1).min(stats.w);
+    let y1 = (y + radius + 1).min(stats.h);
+    let n = ((x1 - x0) * (y1 - y0)) as f64;
+
+    let mean_a = stats.rect_sum(&stats.sum_a, x0, y0, x1, y1) / n;
+    let mean_b = stats.rect_sum(&stats.sum_b, x0, y0, x1, y1) / n;
+    let var_a = stats.rect_sum(&stats.sum_aa, x0, y0, x1, y1) / n - mean_a * mean_a;
+    let var_b = stats.rect_sum(&stats.sum_bb, x0, y0, x1, y1) / n - mean_b * mean_b;
+    let cov = stats.rect_sum(&stats.sum_ab, x0, y0, x1, y1) / n - mean_a * mean_b;
+
+    let denom = (var_a.max(0.0) * var_b.max(0.0)).sqrt();
+    if denom <= 1.0e-12 {
+        return 0.0;
+    }
+    (cov / denom).clamp(-1.0, 1.0) as f32
+}
+
+// Below a few ULPs the `b.abs() <= eps` style guards stop catching zeros.
+fn effective_epsilon(epsilon: f32) -> f32 {
+    epsilon.max(f32::EPSILON * 16.0)
+}
+
+// Use Original Alpha: premultiplies the result by `alpha` and replaces its alpha.
+fn apply_original_alpha(px: PixelF32, alpha: f32) -> PixelF32 {
+    let alpha = finite_or_zero(alpha).clamp(0.0, 1.0);
+    PixelF32 {
+        red: px.red * alpha,
+        green: px.green * alpha,
+        blue: px.blue * alpha,
+        alpha,
+    }
+}
+
+fn finite_or_zero(v: f32) -> f32 {
+    if v.is_finite() { v } else { 0.0 }
+}
+
+fn finite_pixel(px: PixelF32) -> PixelF32 {
+    PixelF32 {
+        red: finite_or_zero(px.red),
+        green: finite_or_zero(px.green),
+        blue: finite_or_zero(px.blue),
+        alpha: finite_or_zero(px.alpha),
+    }
+}
+
+fn gray_pixel(v: f32) -> PixelF32 {
+    PixelF32 {
+        red: v,
+        green: v,
+        blue: v,
+        alpha: 1.0,
+    }
+}
+
 fn fill_pixel(v: f32) -> PixelF32 {
     PixelF32 {
         red: v,
@@ -1013,6 +2317,44 @@ fn fill_pixel(v: f32) -> PixelF32 {
     }
 }
 
+// Per-channel gain and offset on the final result, before clamping.
+struct PostAdjust {
+    gain: [f32; 4],
+    offset: [f32; 4],
+}
+
+impl PostAdjust {
+    fn new(params: &Parameters<Params>) -> Result<Self, Error> {
+        let mut adjust = Self {
+            gain: [1.0; 4],
+            offset: [0.0; 4],
+        };
+        for (i, (gain, offset)) in POST_ADJUST_PARAMS.into_iter().enumerate() {
+            adjust.gain[i] = params.get(gain)?.as_float_slider()?.value() as f32;
+            adjust.offset[i] = params.get(offset)?.as_float_slider()?.value() as f32;
+        }
+        Ok(adjust)
+    }
+
+    fn apply(&self, px: PixelF32, clamp_01: bool) -> PixelF32 {
+        // Identity pairs are skipped so the defaults leave every bit (even -0.0) as is.
+        let adjust = |v: f32, i: usize| {
+            let v = if self.gain[i] != 1.0 || self.offset[i] != 0.0 {
+                v * self.gain[i] + self.offset[i]
+            } else {
+                v
+            };
+            sanitize_output(v, clamp_01)
+        };
+        PixelF32 {
+            red: adjust(px.red, 0),
+            green: adjust(px.green, 1),
+            blue: adjust(px.blue, 2),
+            alpha: adjust(px.alpha, 3),
+        }
+    }
+}
+
 fn sanitize_output(mut v: f32, clamp_01: bool) -> f32 {
     if !v.is_finite() {
         v = 0.0;
@@ -1023,20 +2365,742 @@ fn sanitize_output(mut v: f32, clamp_01: bool) -> f32 {
     v
 }
 
-fn sample_input(
-    x: usize,
-    y: usize,
-    use_layer: bool,
-    layer: Option<&Layer>,
-    world_type: Option<ae::aegp::WorldType>,
-    value: f32,
-) -> PixelF32 {
-    if use_layer && let (Some(layer), Some(world_type)) = (layer, world_type) {
-        let bx = x.min(layer.width().saturating_sub(1));
-        let by = y.min(layer.height().saturating_sub(1));
-        return read_pixel_f32(layer, world_type, bx, by);
+enum Operand {
+    Pixels { data: Vec<PixelF32>, width: usize },
+    Constant(PixelF32),
+    // Grey per-pixel noise in 0..1 with opaque alpha.
+    Random { seed: u32 },
+}
+
+impl Operand {
+    // Resolves the operand layer onto the `w` x `h` output grid according to `fit`.
+    // `origin` is where the output's top-left falls in the layer's buffer; the
+    // edge fits honour it, Stretch and Center are relative to the output.
+    fn new(
+        use_layer: bool,
+        layer: Option<&Layer>,
+        value: f32,
+        fit: OperandFit,
+        (w, h): (usize, usize),
+        (ox, oy): (isize, isize),
+    ) -> Self {
+        let Some(layer) = layer.filter(|l| use_layer && l.width() > 0 && l.height() > 0) else {
+            return Self::Constant(fill_pixel(value));
+        };
+        let world_type = layer.world_type();
+        let (lw, lh) = (layer.width(), layer.height());
+        let mut data = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                data.push(match fit_sample(fit, (x, y), (w, h), (lw, lh), (ox, oy)) {
+                    FitSample::Pixel(lx, ly) => read_pixel_f32(layer, world_type, lx, ly),
+                    FitSample::Bilinear(u, v) => sample_bilinear(layer, world_type, u, v),
+                    FitSample::Transparent => PixelF32 {
+                        red: 0.0,
+                        green: 0.0,
+                        blue: 0.0,
+                        alpha: 0.0,
+                    },
+                });
+            }
+        }
+        Self::Pixels { data, width: w }
+    }
+
+    fn sample(&self, x: usize, y: usize) -> PixelF32 {
+        match self {
+            Self::Pixels { data, width } => data[y * width + x],
+            Self::Constant(px) => *px,
+            Self::Random { seed } => {
+                let v = rand01(pixel_hash(x, y, *seed));
+                PixelF32 {
+                    red: v,
+                    green: v,
+                    blue: v,
+                    alpha: 1.0,
+                }
+            }
+        }
+    }
+
+    fn mean_luma(&self, w: usize, h: usize, weights: [f32; 3]) -> f32 {
+        match self {
+            Self::Pixels { data, .. } if w * h > 0 => {
+                let sum: f64 = data.iter().map(|px| luma(*px, weights) as f64).sum();
+                finite_or_zero((sum / (w * h) as f64) as f32)
+            }
+            Self::Pixels { .. } => 0.0,
+            Self::Constant(px) => finite_or_zero(luma(*px, weights)),
+            Self::Random { .. } => 0.5,
+        }
+    }
+}
+
+// Layer-space top-left of each SmartRender buffer, recorded in SmartPreRender
+// whenever an operand layer is checked out. The output matches A unless the
+// operands widen it.
+#[derive(Clone, Copy)]
+struct Extent {
+    out: (i32, i32),
+    a: (i32, i32),
+    operands: [(i32, i32); 2],
+}
+
+impl Extent {
+    // Position of A's top-left pixel on the output grid.
+    fn a_offset(&self) -> (isize, isize) {
+        (
+            (self.a.0 - self.out.0) as isize,
+            (self.a.1 - self.out.1) as isize,
+        )
+    }
+
+    // Position of the output's top-left pixel in operand `slot`'s buffer.
+    fn operand_offset(&self, slot: usize) -> (isize, isize) {
+        let (x, y) = self.operands[slot];
+        ((self.out.0 - x) as isize, (self.out.1 - y) as isize)
+    }
+}
+
+// Reads A onto the `w` x `h` output grid with its top-left at `offset`.
+fn decode_a(
+    layer: &Layer,
+    (w, h): (usize, usize),
+    (ox, oy): (isize, isize),
+    outside: OutsideA,
+) -> Vec<PixelF32> {
+    let world_type = layer.world_type();
+    let (lw, lh) = (layer.width() as isize, layer.height() as isize);
+    let mut data = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let (lx, ly) = (x as isize - ox, y as isize - oy);
+            let inside = lx >= 0 && ly >= 0 && lx < lw && ly < lh;
+            let px = match outside {
+                _ if inside => read_pixel_f32(layer, world_type, lx as usize, ly as usize),
+                OutsideA::ClampEdge if lw > 0 && lh > 0 => read_pixel_f32(
+                    layer,
+                    world_type,
+                    lx.clamp(0, lw - 1) as usize,
+                    ly.clamp(0, lh - 1) as usize,
+                ),
+                _ => PixelF32 {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                    alpha: 0.0,
+                },
+            };
+            data.push(px);
+        }
     }
-    fill_pixel(value)
+    data
+}
+
+fn pixel_hash(x: usize, y: usize, seed: u32) -> u32 {
+    let h = hash_u32(seed ^ (x as u32).wrapping_mul(0x85EB_CA6B));
+    hash_u32(h ^ (y as u32).wrapping_mul(0xC2B2_AE35))
+}
+
+const HISTOGRAM_BINS: usize = 1024;
+// Nearly transparent pixels are left out of the histogram.
+const HISTOGRAM_ALPHA_THRESHOLD: f32 = 1.0 / 255.0;
+
+// Normalized cumulative luminance histogram of A over 0..1.
+struct LumaCdf {
+    cdf: Option<Vec<f32>>,
+}
+
+impl LumaCdf {
+    fn new(a_pixels: &[PixelF32], w: usize, h: usize, weights: [f32; 3]) -> Self {
+        let mut counts = vec![0u64; HISTOGRAM_BINS];
+        for y in 0..h {
+            for x in 0..w {
+                let px = a_pixels[y * w + x];
+                if px.alpha >= HISTOGRAM_ALPHA_THRESHOLD {
+                    counts[luma_bin(luma(px, weights))] += 1;
+                }
+            }
+        }
+
+        // A single occupied bin (or none) has nothing to spread out.
+        let total: u64 = counts.iter().sum();
+        let first = counts.iter().copied().find(|&c| c > 0).unwrap_or(0);
+        let range = total.saturating_sub(first);
+        if range == 0 {
+            return Self { cdf: None };
+        }
+
+        let mut running = 0u64;
+        let cdf = counts
+            .iter()
+            .map(|&c| {
+                running += c;
+                running.saturating_sub(first) as f32 / range as f32
+            })
+            .collect();
+        Self { cdf: Some(cdf) }
+    }
+
+    fn map(&self, luma: f32) -> f32 {
+        self.cdf.as_ref().map_or(luma, |cdf| cdf[luma_bin(luma)])
+    }
+}
+
+fn luma_bin(luma: f32) -> usize {
+    let v = if luma.is_finite() {
+        luma.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((v * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+fn kernel_preset(preset: i32) -> Option<[[f32; 3]; 3]> {
+    match preset {
+        2 => Some([[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]]),
+        3 => Some([[0.0, -1.0, 0.0], [-1.0, 5.0, -1.0], [0.0, -1.0, 0.0]]),
+        4 => Some([[-1.0, -1.0, -1.0], [-1.0, 8.0, -1.0], [-1.0, -1.0, -1.0]]),
+        5 => Some([[-2.0, -1.0, 0.0], [-1.0, 1.0, 1.0], [0.0, 1.0, 2.0]]),
+        6 => Some([[1.0 / 9.0; 3]; 3]),
+        _ => None,
+    }
+}
+
+// RGB is convolved; alpha is kept from A.
+fn convolve_layer(
+    a_pixels: &[PixelF32],
+    w: usize,
+    h: usize,
+    kernel: &[[f32; 3]; 3],
+    normalize: bool,
+) -> Vec<PixelF32> {
+    let pixels = a_pixels.to_vec();
+    let rgb = |px: &PixelF32| [px.red, px.green, px.blue];
+
+    let mut out = pixels.clone();
+    for channel in 0..3 {
+        let data: Vec<f32> = pixels.iter().map(|px| rgb(px)[channel]).collect();
+        let result = apply_conv_matrix(&data, w, h, kernel, normalize);
+        for (px, v) in out.iter_mut().zip(result) {
+            match channel {
+                0 => px.red = v,
+                1 => px.green = v,
+                _ => px.blue = v,
+            }
+        }
+    }
+    out
+}
+
+// Larger kernel layers are cropped around their centre.
+const FREQ_KERNEL_MAX: usize = 256;
+
+// Luminance of the kernel layer B, normalized to sum to one.
+struct FreqKernel {
+    data: Vec<f32>,
+    width: usize,
+    height: usize,
+}
+
+impl FreqKernel {
+    // Without a layer, the kernel is the single value B, i.e. an identity after
+    // normalization.
+    fn new(layer: Option<&Layer>, value: f32, weights: [f32; 3]) -> Self {
+        let Some(layer) = layer.filter(|l| l.width() > 0 && l.height() > 0) else {
+            return Self::normalized(vec![finite_or_zero(value)], 1, 1);
+        };
+        let world_type = layer.world_type();
+        let width = layer.width().min(FREQ_KERNEL_MAX);
+        let height = layer.height().min(FREQ_KERNEL_MAX);
+        let x0 = (layer.width() - width) / 2;
+        let y0 = (layer.height() - height) / 2;
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let px = finite_pixel(read_pixel_f32(layer, world_type, x0 + x, y0 + y));
+                data.push(luma(px, weights));
+            }
+        }
+        Self::normalized(data, width, height)
+    }
+
+    fn normalized(mut data: Vec<f32>, width: usize, height: usize) -> Self {
+        let sum: f32 = data.iter().sum();
+        if sum.abs() > 1.0e-6 {
+            for v in &mut data {
+                *v /= sum;
+            }
+        }
+        Self {
+            data,
+            width,
+            height,
+        }
+    }
+}
+
+// Convolves the RGB channels of A with `kernel` via FFT (alpha is kept from A).
+// A is padded to a power of two with enough edge-clamped margin that the
+// circular convolution does not wrap.
+fn frequency_convolve(
+    a_pixels: &[PixelF32],
+    w: usize,
+    h: usize,
+    kernel: &FreqKernel,
+) -> Vec<PixelF32> {
+    let pixels: Vec<PixelF32> = a_pixels.iter().map(|px| finite_pixel(*px)).collect();
+    let pw = (w + kernel.width).next_power_of_two();
+    let ph = (h + kernel.height).next_power_of_two();
+
+    // Kernel centre at the origin, so the result is not shifted.
+    let (cx, cy) = (kernel.width / 2, kernel.height / 2);
+    let mut kernel_plane = vec![0.0f32; pw * ph];
+    for ky in 0..kernel.height {
+        for kx in 0..kernel.width {
+            let x = (kx + pw - cx) % pw;
+            let y = (ky + ph - cy) % ph;
+            kernel_plane[y * pw + x] = kernel.data[ky * kernel.width + kx];
+        }
+    }
+    let kernel_spectrum = fft2(&kernel_plane, pw, ph);
+
+    let mut out = pixels.clone();
+    for channel in 0..3 {
+        let plane = padded_plane(&pixels, (w, h), (pw, ph), channel);
+        let mut spectrum: Spectrum = fft2(&plane, pw, ph);
+        spectrum.multiply(&kernel_spectrum);
+        let result = ifft2(spectrum);
+        for (i, px) in out.iter_mut().enumerate() {
+            let v = result[(i / w) * pw + i % w];
+            match channel {
+                0 => px.red = v,
+                1 => px.green = v,
+                _ => px.blue = v,
+            }
+        }
+    }
+    out
+}
+
+// One RGB channel of a `w` x `h` image in a `pw` x `ph` power-of-two plane.
+// Padding past the right/bottom edge: the first half clamps to that edge, the
+// rest wraps around to stand in for the left/top margin.
+fn padded_plane(
+    pixels: &[PixelF32],
+    (w, h): (usize, usize),
+    (pw, ph): (usize, usize),
+    channel: usize,
+) -> Vec<f32> {
+    let pad_index = |i: usize, len: usize, padded: usize| {
+        if i < len {
+            i
+        } else if i < len + (padded - len) / 2 {
+            len - 1
+        } else {
+            0
+        }
+    };
+    let rgb = |px: &PixelF32| [px.red, px.green, px.blue];
+    let mut plane = vec![0.0f32; pw * ph];
+    for y in 0..ph {
+        let sy = pad_index(y, h, ph);
+        for x in 0..pw {
+            let sx = pad_index(x, w, pw);
+            plane[y * pw + x] = rgb(&pixels[sy * w + sx])[channel];
+        }
+    }
+    plane
+}
+
+// Edge margin added before rounding up to a power of two, so the image's own
+// left/right and top/bottom edges do not meet in the circular transform.
+const SPECTRAL_MIX_MARGIN: usize = 16;
+
+// Blends the RGB spectra of A and B band by band (alpha is kept from A).
+// `band` is the crossover and its softness, both as a fraction of Nyquist.
+fn spectral_mix(
+    a_pixels: &[PixelF32],
+    operand_b: &Operand,
+    (w, h): (usize, usize),
+    (crossover, softness): (f32, f32),
+    mode: SpectralMixMode,
+) -> Vec<PixelF32> {
+    let a_pixels: Vec<PixelF32> = a_pixels.iter().map(|px| finite_pixel(*px)).collect();
+    let b_pixels: Vec<PixelF32> = (0..w * h)
+        .map(|i| finite_pixel(operand_b.sample(i % w, i / w)))
+        .collect();
+    let pw = (w + SPECTRAL_MIX_MARGIN).next_power_of_two();
+    let ph = (h + SPECTRAL_MIX_MARGIN).next_power_of_two();
+
+    // Weight of A in the low and the high band.
+    let (low_a, high_a) = match mode {
+        SpectralMixMode::LowAHighB => (1.0, 0.0),
+        SpectralMixMode::LowBHighA => (0.0, 1.0),
+        SpectralMixMode::CrossFadeA => (1.0, 0.5),
+        SpectralMixMode::CrossFadeB => (0.0, 0.5),
+    };
+    let axis_freq = |k: usize, n: usize| k.min(n - k) as f32 / (n as f32 * 0.5);
+    let weight_a: Vec<f32> = (0..pw * ph)
+        .map(|i| {
+            let r = axis_freq(i % pw, pw).hypot(axis_freq(i / pw, ph)).min(1.0);
+            let low = 1.0 - smoothstep(crossover - softness * 0.5, crossover + softness * 0.5, r);
+            high_a + (low_a - high_a) * low
+        })
+        .collect();
+
+    let mut out = a_pixels.clone();
+    for channel in 0..3 {
+        let mut spectrum = fft2(&padded_plane(&a_pixels, (w, h), (pw, ph), channel), pw, ph);
+        let spectrum_b = fft2(&padded_plane(&b_pixels, (w, h), (pw, ph), channel), pw, ph);
+        for (i, wa) in weight_a.iter().enumerate() {
+            spectrum.re[i] = spectrum.re[i] * wa + spectrum_b.re[i] * (1.0 - wa);
+            spectrum.im[i] = spectrum.im[i] * wa + spectrum_b.im[i] * (1.0 - wa);
+        }
+        let result = ifft2(spectrum);
+        for (i, px) in out.iter_mut().enumerate() {
+            let v = result[(i / w) * pw + i % w];
+            match channel {
+                0 => px.red = v,
+                1 => px.green = v,
+                _ => px.blue = v,
+            }
+        }
+    }
+    out
+}
+
+const STYLE_OCTAVES: usize = 8;
+// Keeps near-empty bands of A from being blown up into noise.
+const STYLE_MAX_GAIN: f32 = 16.0;
+
+// Octave of a spectrum bin, counted up from the lowest band; octave 7 ends at
+// Nyquist. DC is left out so A keeps its mean colour.
+fn spectral_octave(i: usize, width: usize, height: usize) -> Option<usize> {
+    let axis_freq = |k: usize, n: usize| k.min(n - k) as f32 / (n as f32 * 0.5);
+    let (fx, fy) = (axis_freq(i % width, width), axis_freq(i / width, height));
+    // Squared radius against quartering edges avoids a sqrt and log per bin.
+    let r2 = fx * fx + fy * fy;
+    if r2 <= 0.0 {
+        return None;
+    }
+    let mut octave = STYLE_OCTAVES - 1;
+    let mut edge = 0.25;
+    while octave > 0 && r2 <= edge {
+        octave -= 1;
+        edge *= 0.25;
+    }
+    Some(octave)
+}
+
+// Sum of squared magnitudes of `spectrum` (a power spectrum) within `octave`.
+fn spectral_octave_sse(spectrum: &[f32], width: usize, height: usize, octave: usize) -> f32 {
+    let sum: f64 = spectrum
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| spectral_octave(*i, width, height) == Some(octave))
+        .map(|(_, p)| *p as f64)
+        .sum();
+    sum as f32
+}
+
+fn spectral_octave_rescale(
+    real: &mut [f32],
+    imag: &mut [f32],
+    scale: f32,
+    octave: usize,
+    width: usize,
+    height: usize,
+) {
+    for i in 0..real.len() {
+        if spectral_octave(i, width, height) == Some(octave) {
+            real[i] *= scale;
+            imag[i] *= scale;
+        }
+    }
+}
+
+// Per RGB channel, scales each octave band of A so its RMS matches B's (alpha
+// is kept from A). Both share one padded grid, so equal bin counts cancel.
+fn style_match(
+    a_pixels: &[PixelF32],
+    operand_b: &Operand,
+    (w, h): (usize, usize),
+) -> Vec<PixelF32> {
+    let a_pixels: Vec<PixelF32> = a_pixels.iter().map(|px| finite_pixel(*px)).collect();
+    let b_pixels: Vec<PixelF32> = (0..w * h)
+        .map(|i| finite_pixel(operand_b.sample(i % w, i / w)))
+        .collect();
+    let pw = (w + SPECTRAL_MIX_MARGIN).next_power_of_two();
+    let ph = (h + SPECTRAL_MIX_MARGIN).next_power_of_two();
+    let power = |s: &Spectrum| -> Vec<f32> {
+        s.re.iter()
+            .zip(&s.im)
+            .map(|(re, im)| re * re + im * im)
+            .collect()
+    };
+
+    let mut out = a_pixels.clone();
+    for channel in 0..3 {
+        let mut spectrum = fft2(&padded_plane(&a_pixels, (w, h), (pw, ph), channel), pw, ph);
+        let spectrum_b = fft2(&padded_plane(&b_pixels, (w, h), (pw, ph), channel), pw, ph);
+        let (power_a, power_b) = (power(&spectrum), power(&spectrum_b));
+        for octave in 0..STYLE_OCTAVES {
+            let sse_a = spectral_octave_sse(&power_a, pw, ph, octave);
+            let sse_b = spectral_octave_sse(&power_b, pw, ph, octave);
+            if sse_a <= f32::EPSILON {
+                continue;
+            }
+            let scale = finite_or_zero((sse_b / sse_a).sqrt()).min(STYLE_MAX_GAIN);
+            spectral_octave_rescale(&mut spectrum.re, &mut spectrum.im, scale, octave, pw, ph);
+        }
+        let result = ifft2(spectrum);
+        for (i, px) in out.iter_mut().enumerate() {
+            let v = result[(i / w) * pw + i % w];
+            match channel {
+                0 => px.red = v,
+                1 => px.green = v,
+                _ => px.blue = v,
+            }
+        }
+    }
+    out
+}
+
+// Sobel weights scaled by 1/8 so a ramp rising by s per pixel reads as s.
+const SOBEL_X: [[f32; 3]; 3] = [
+    [-0.125, 0.0, 0.125],
+    [-0.25, 0.0, 0.25],
+    [-0.125, 0.0, 0.125],
+];
+const SOBEL_Y: [[f32; 3]; 3] = [
+    [-0.125, -0.25, -0.125],
+    [0.0, 0.0, 0.0],
+    [0.125, 0.25, 0.125],
+];
+const LAPLACIAN: [[f32; 3]; 3] = [[0.0, 1.0, 0.0], [1.0, -4.0, 1.0], [0.0, 1.0, 0.0]];
+
+// Per-channel spatial derivative of A (RGB only; alpha is kept from A), edge-clamped.
+fn derivative_layer(a_pixels: &[PixelF32], w: usize, h: usize, op: MathOp) -> Vec<PixelF32> {
+    let pixels: Vec<PixelF32> = a_pixels.iter().map(|px| finite_pixel(*px)).collect();
+    let rgb = |px: &PixelF32| [px.red, px.green, px.blue];
+
+    let mut out = pixels.clone();
+    for channel in 0..3 {
+        let data: Vec<f32> = pixels.iter().map(|px| rgb(px)[channel]).collect();
+        let result = match op {
+            MathOp::GradientX => apply_conv_matrix(&data, w, h, &SOBEL_X, false),
+            MathOp::GradientY => apply_conv_matrix(&data, w, h, &SOBEL_Y, false),
+            MathOp::GradientMagnitude => {
+                let gx = apply_conv_matrix(&data, w, h, &SOBEL_X, false);
+                let gy = apply_conv_matrix(&data, w, h, &SOBEL_Y, false);
+                gx.iter().zip(&gy).map(|(x, y)| x.hypot(*y)).collect()
+            }
+            _ => apply_conv_matrix(&data, w, h, &LAPLACIAN, false),
+        };
+        for (px, v) in out.iter_mut().zip(result) {
+            match channel {
+                0 => px.red = v,
+                1 => px.green = v,
+                _ => px.blue = v,
+            }
+        }
+    }
+    out
+}
+
+fn apply_conv_matrix(
+    data: &[f32],
+    w: usize,
+    h: usize,
+    kernel: &[[f32; 3]; 3],
+    normalize: bool,
+) -> Vec<f32> {
+    let mut k = *kernel;
+    let sum: f32 = k.iter().flatten().sum();
+    if normalize && sum > 1.0e-6 {
+        for v in k.iter_mut().flatten() {
+            *v /= sum;
+        }
+    }
+
+    if let Some((col, row)) = rank1_factors(&k) {
+        // Separable kernel: a vertical pass with `col`, then a horizontal pass with `row`.
+        let mut tmp = vec![0.0f32; w * h];
+        for y in 0..h {
+            for x in 0..w {
+                tmp[y * w + x] = (0..3)
+                    .map(|i| col[i] * data[clamp_offset(y, i, h) * w + x])
+                    .sum();
+            }
+        }
+        let mut out = vec![0.0f32; w * h];
+        for y in 0..h {
+            for x in 0..w {
+                out[y * w + x] = (0..3)
+                    .map(|j| row[j] * tmp[y * w + clamp_offset(x, j, w)])
+                    .sum();
+            }
+        }
+        return out;
+    }
+
+    let mut out = vec![0.0f32; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let mut acc = 0.0;
+            for (i, krow) in k.iter().enumerate() {
+                let sy = clamp_offset(y, i, h);
+                for (j, kv) in krow.iter().enumerate() {
+                    acc += kv * data[sy * w + clamp_offset(x, j, w)];
+                }
+            }
+            out[y * w + x] = acc;
+        }
+    }
+    out
+}
+
+// Splits `k` into column x row vectors when it is rank 1. For a rank-1 matrix this
+// outer product equals its SVD rank-1 approximation, so an exact match is the test.
+fn rank1_factors(k: &[[f32; 3]; 3]) -> Option<([f32; 3], [f32; 3])> {
+    let (mut pi, mut pj, mut pivot) = (0, 0, 0.0f32);
+    for (i, row) in k.iter().enumerate() {
+        for (j, v) in row.iter().enumerate() {
+            if v.abs() > pivot.abs() {
+                (pi, pj, pivot) = (i, j, *v);
+            }
+        }
+    }
+    if pivot == 0.0 {
+        return None;
+    }
+    let col = [k[0][pj], k[1][pj], k[2][pj]];
+    let row = k[pi].map(|v| v / pivot);
+    let tolerance = pivot.abs() * 1.0e-5;
+    let matches = (0..3).all(|i| (0..3).all(|j| (col[i] * row[j] - k[i][j]).abs() <= tolerance));
+    matches.then_some((col, row))
+}
+
+// Index of tap `k` (0..3, centered on 1) around `i`, clamped to `0..len`.
+fn clamp_offset(i: usize, k: usize, len: usize) -> usize {
+    (i + k).saturating_sub(1).min(len - 1)
+}
+
+// Where output pixel (x, y) reads an `lw` x `lh` operand layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FitSample {
+    Pixel(usize, usize),
+    Bilinear(f32, f32),
+    Transparent,
+}
+
+fn fit_sample(
+    fit: OperandFit,
+    (x, y): (usize, usize),
+    (w, h): (usize, usize),
+    (lw, lh): (usize, usize),
+    (ox, oy): (isize, isize),
+) -> FitSample {
+    let (lx, ly) = (x as isize + ox, y as isize + oy);
+    match fit {
+        OperandFit::ClampEdge => FitSample::Pixel(
+            lx.clamp(0, lw as isize - 1) as usize,
+            ly.clamp(0, lh as isize - 1) as usize,
+        ),
+        OperandFit::Tile => FitSample::Pixel(
+            lx.rem_euclid(lw as isize) as usize,
+            ly.rem_euclid(lh as isize) as usize,
+        ),
+        OperandFit::Mirror => FitSample::Pixel(
+            mirror_index(lx.rem_euclid(2 * lw as isize) as usize, lw),
+            mirror_index(ly.rem_euclid(2 * lh as isize) as usize, lh),
+        ),
+        OperandFit::Stretch => FitSample::Bilinear(
+            ((x as f32 + 0.5) * lw as f32 / w as f32 - 0.5).max(0.0),
+            ((y as f32 + 0.5) * lh as f32 / h as f32 - 0.5).max(0.0),
+        ),
+        OperandFit::Center => {
+            let sx = x as isize - (w as isize - lw as isize) / 2;
+            let sy = y as isize - (h as isize - lh as isize) / 2;
+            if sx < 0 || sy < 0 || sx >= lw as isize || sy >= lh as isize {
+                FitSample::Transparent
+            } else {
+                FitSample::Pixel(sx as usize, sy as usize)
+            }
+        }
+    }
+}
+
+fn mirror_index(i: usize, len: usize) -> usize {
+    let m = i % (len * 2);
+    if m < len { m } else { len * 2 - 1 - m }
+}
+
+fn sample_bilinear(layer: &Layer, world_type: ae::aegp::WorldType, u: f32, v: f32) -> PixelF32 {
+    let max_x = layer.width() - 1;
+    let max_y = layer.height() - 1;
+    let x0 = (u.floor() as usize).min(max_x);
+    let y0 = (v.floor() as usize).min(max_y);
+    let x1 = (x0 + 1).min(max_x);
+    let y1 = (y0 + 1).min(max_y);
+    let tx = u - u.floor();
+    let ty = v - v.floor();
+
+    let p00 = read_pixel_f32(layer, world_type, x0, y0);
+    let p10 = read_pixel_f32(layer, world_type, x1, y0);
+    let p01 = read_pixel_f32(layer, world_type, x0, y1);
+    let p11 = read_pixel_f32(layer, world_type, x1, y1);
+    let mix = |a: f32, b: f32, c: f32, d: f32| {
+        let top = a + (b - a) * tx;
+        let bottom = c + (d - c) * tx;
+        top + (bottom - top) * ty
+    };
+    PixelF32 {
+        red: mix(p00.red, p10.red, p01.red, p11.red),
+        green: mix(p00.green, p10.green, p01.green, p11.green),
+        blue: mix(p00.blue, p10.blue, p01.blue, p11.blue),
+        alpha: mix(p00.alpha, p10.alpha, p01.alpha, p11.alpha),
+    }
+}
+
+// Largest Detail Radius (the Gaussian sigma, in pixels) of Frequency Match.
+const FREQ_MATCH_MAX_RADIUS: f32 = 200.0;
+
+// Frequency separation bands, per RGB channel; do_render adds `high_b` scaled by
+// C to `low_a`. Alpha of both is unused.
+struct FreqBands {
+    low_a: Vec<PixelF32>,
+    high_b: Vec<PixelF32>,
+}
+
+fn frequency_match(
+    a_pixels: &[PixelF32],
+    operand_b: &Operand,
+    w: usize,
+    h: usize,
+    sigma: f32,
+) -> FreqBands {
+    let b_pixels: Vec<PixelF32> = (0..w * h).map(|i| operand_b.sample(i % w, i / w)).collect();
+    let rgb = |px: &PixelF32| [px.red, px.green, px.blue];
+
+    let mut low_a = a_pixels.to_vec();
+    let mut high_b = b_pixels.clone();
+    for channel in 0..3 {
+        let a: Vec<f32> = a_pixels.iter().map(|px| rgb(px)[channel]).collect();
+        let b: Vec<f32> = b_pixels.iter().map(|px| rgb(px)[channel]).collect();
+        let blurred_a = gaussian_blur(&a, w, h, sigma);
+        let blurred_b = gaussian_blur(&b, w, h, sigma);
+        for (i, (low, high)) in low_a.iter_mut().zip(high_b.iter_mut()).enumerate() {
+            let detail = b[i] - blurred_b[i];
+            match channel {
+                0 => (low.red, high.red) = (blurred_a[i], detail),
+                1 => (low.green, high.green) = (blurred_a[i], detail),
+                _ => (low.blue, high.blue) = (blurred_a[i], detail),
+            }
+        }
+    }
+    FreqBands { low_a, high_b }
 }
 
 fn read_pixel_f32(layer: &Layer, world_type: ae::aegp::WorldType, x: usize, y: usize) -> PixelF32 {
@@ -1046,3 +3110,204 @@ fn read_pixel_f32(layer: &Layer, world_type: ae::aegp::WorldType, x: usize, y: u
         ae::aegp::WorldType::F32 | ae::aegp::WorldType::None => *layer.as_pixel32(x, y),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_project_popups_decode_as_saved() {
+        // 0.1.0 kept no sequence data, so AE builds the instance from its default.
+        let instance = Instance::default();
+        assert_eq!(instance.params_layout, LEGACY_PARAMS_LAYOUT);
+        assert_eq!(instance.flatten().expect("flatten").0, LEGACY_PARAMS_LAYOUT);
+
+        let fixture = include_str!("../fixtures/params_0_1_0.txt");
+        let mut checked = 0;
+        for line in fixture
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let [param, index, meaning] = line.split(' ').collect::<Vec<_>>()[..] else {
+                panic!("bad fixture line {line:?}");
+            };
+            let id = match param {
+                "Operation" => Params::Operation,
+                "InputBSource" => Params::InputBSource,
+                "InputCSource" => Params::InputCSource,
+                _ => panic!("unknown param {param}"),
+            };
+            let saved: i32 = index.parse().expect("saved index");
+            let value = remap_popup(instance.params_layout, id, saved);
+            let decoded = match id {
+                Params::Operation => format!("{:?}", math_op_from_popup(value)),
+                _ => format!("{:?}", input_source_from_popup(value)),
+            };
+            assert_eq!(decoded, meaning, "{param} {saved}");
+            checked += 1;
+        }
+        assert_eq!(checked, 43);
+    }
+
+    #[test]
+    fn sequence_data_versions_map_to_params_layouts() {
+        for (version, layout) in [
+            (0, LEGACY_PARAMS_LAYOUT),
+            (1, 1),
+            (2, 2),
+            (9, PARAMS_LAYOUT),
+        ] {
+            let instance = Instance::unflatten(version, &[]).expect("unflatten");
+            assert_eq!(instance.params_layout, layout, "version {version}");
+        }
+    }
+
+    fn grey(v: f32) -> PixelF32 {
+        PixelF32 {
+            red: v,
+            green: v,
+            blue: v,
+            alpha: 1.0,
+        }
+    }
+
+    fn assert_close(got: f32, want: f32, tolerance: f32) {
+        assert!((got - want).abs() <= tolerance, "got {got}, want {want}");
+    }
+
+    #[test]
+    fn luma_standards_weigh_primaries_by_their_coefficients() {
+        let green = PixelF32 {
+            red: 0.0,
+            green: 1.0,
+            blue: 0.0,
+            alpha: 1.0,
+        };
+        let expected = [(1, 0.7152), (2, 0.587), (3, 0.6780), (4, 1.0 / 3.0)];
+        for (index, want) in expected {
+            let weights = luma_weights(luma_standard_from_popup(index));
+            assert_close(luma(green, weights), want, 1.0e-6);
+            // Every standard keeps white at 1.
+            assert_close(luma(grey(1.0), weights), 1.0, 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn operand_fit_maps_output_pixels_onto_the_layer() {
+        // A 4x2 layer under a 6x4 output with no offset.
+        let at = |fit, x, y| fit_sample(fit, (x, y), (6, 4), (4, 2), (0, 0));
+        assert_eq!(at(OperandFit::ClampEdge, 5, 3), FitSample::Pixel(3, 1));
+        assert_eq!(at(OperandFit::Tile, 5, 3), FitSample::Pixel(1, 1));
+        assert_eq!(at(OperandFit::Mirror, 4, 2), FitSample::Pixel(3, 1));
+        assert_eq!(at(OperandFit::Mirror, 5, 3), FitSample::Pixel(2, 0));
+        assert_eq!(at(OperandFit::Center, 0, 0), FitSample::Transparent);
+        assert_eq!(at(OperandFit::Center, 1, 1), FitSample::Pixel(0, 0));
+        assert_eq!(at(OperandFit::Center, 5, 2), FitSample::Transparent);
+        assert_eq!(at(OperandFit::Stretch, 0, 0), FitSample::Bilinear(0.0, 0.0));
+        assert_eq!(
+            at(OperandFit::Stretch, 5, 3),
+            FitSample::Bilinear(3.1666667, 1.25)
+        );
+
+        // The edge fits honour the origin of the output in the layer.
+        let shifted = fit_sample(OperandFit::Tile, (0, 0), (6, 4), (4, 2), (-1, -1));
+        assert_eq!(shifted, FitSample::Pixel(3, 1));
+    }
+
+    #[test]
+    fn histogram_equalize_spreads_opaque_levels_evenly() {
+        let weights = luma_weights(LumaStandard::Rec709);
+        let mut pixels: Vec<PixelF32> = [0.1, 0.2, 0.3, 0.4].map(grey).to_vec();
+        // Transparent pixels do not count towards the histogram.
+        pixels.push(PixelF32 {
+            alpha: 0.0,
+            ..grey(0.35)
+        });
+        let cdf = LumaCdf::new(&pixels, 5, 1, weights);
+        for (level, want) in [(0.1, 0.0), (0.2, 1.0 / 3.0), (0.3, 2.0 / 3.0), (0.4, 1.0)] {
+            assert_close(cdf.map(level), want, 1.0e-6);
+        }
+
+        // A flat image has nothing to spread and passes through.
+        let flat = LumaCdf::new(&[grey(0.5); 4], 2, 2, weights);
+        assert_close(flat.map(0.25), 0.25, 1.0e-6);
+    }
+
+    #[test]
+    fn gradients_and_laplacian_match_known_slopes() {
+        let (w, h) = (5, 3);
+        let plane = |f: fn(f32) -> f32| -> Vec<PixelF32> {
+            (0..w * h).map(|i| grey(f((i % w) as f32))).collect()
+        };
+        let ramp = plane(|x| 0.1 * x);
+        let bowl = plane(|x| 0.01 * x * x);
+        let center = w + 2;
+
+        let expected = [
+            (MathOp::GradientX, &ramp, 0.1),
+            (MathOp::GradientY, &ramp, 0.0),
+            (MathOp::GradientMagnitude, &ramp, 0.1),
+            (MathOp::Laplacian, &ramp, 0.0),
+            (MathOp::Laplacian, &bowl, 0.02),
+        ];
+        for (op, pixels, want) in expected {
+            let d = derivative_layer(pixels, w, h, op);
+            assert_close(d[center].red, want, 1.0e-5);
+            assert_close(d[center].blue, want, 1.0e-5);
+            assert_close(d[center].alpha, 1.0, 0.0);
+        }
+    }
+
+    #[test]
+    fn oklab_mix_of_red_and_green_matches_reference() {
+        let red = PixelF32 {
+            red: 1.0,
+            green: 0.0,
+            blue: 0.0,
+            alpha: 1.0,
+        };
+        let green = PixelF32 {
+            red: 0.0,
+            green: 1.0,
+            blue: 0.0,
+            alpha: 1.0,
+        };
+        // sRGB midpoint from Ottosson's reference OKLab matrices.
+        let mid = mix_result(red, green, 0.5, MixSpace::Oklab, true);
+        assert_close(mid.red, 0.8163, 2.0e-3);
+        assert_close(mid.green, 0.6604, 2.0e-3);
+        assert_close(mid.blue, 0.0018, 2.0e-3);
+        assert_close(mid.alpha, 1.0, 0.0);
+
+        // A linear mix is the plain channel average.
+        let linear = mix_result(red, green, 0.5, MixSpace::Linear, true);
+        assert_close(linear.red, 0.5, 1.0e-6);
+        assert_close(linear.green, 0.5, 1.0e-6);
+    }
+
+    #[test]
+    fn non_finite_inputs_never_reach_the_output() {
+        let bad = [f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+        let epsilon = effective_epsilon(0.0);
+        assert!(epsilon > 0.0);
+        for index in 1..=54 {
+            let op = math_op_from_popup(index);
+            for (a, b, c) in bad
+                .iter()
+                .flat_map(|&v| [(v, 0.0, 0.0), (0.0, v, 0.0), (1.0, 0.0, v), (v, v, v)])
+            {
+                let [a, b, c] = [a, b, c].map(|v| finite_pixel(grey(v)));
+                let v = apply_math(op, a.red, b.red, c.red, epsilon);
+                assert!(v.is_finite(), "{op:?} gave {v}");
+            }
+        }
+
+        // A NaN result or alpha still comes out of Use Original Alpha finite.
+        for alpha in bad {
+            let px = apply_original_alpha(finite_pixel(grey(f32::NAN)), alpha);
+            assert_eq!((px.red, px.alpha), (0.0, 0.0));
+        }
+        let px = apply_original_alpha(grey(0.5), 2.0);
+        assert_eq!((px.red, px.alpha), (0.5, 1.0));
+    }
+}
@@ -15,12 +15,29 @@ pub struct WgpuRenderParams {
     pub randomness: f32,
     pub seed: u32,
     pub distance_metric: u32,
-    pub lp_exp: f32,
+    pub lp_exp: [f32; 3],
+    pub cylinder_radius: f32,
     pub smoothness: f32,
     pub output_type: u32,
     pub w_value: f32,
     pub offset_x: f32,
     pub offset_y: f32,
+    pub wave_amount: f32,
+    pub wave_frequency: f32,
+    pub wave_angle: f32,
+    pub flow_time: f32,
+    pub flow_turbulence: f32,
+    pub jitter_time: f32,
+    pub jitter: f32,
+    pub jitter_frequency: f32,
+    pub edge_thickness: f32,
+    pub edge_softness: f32,
+    pub stipple_radius: f32,
+    pub stipple_softness: f32,
+    pub cell_uv_scale: f32,
+    pub cell_uv_offset: (f32, f32),
+    pub reveal_progress: f32,
+    pub reveal_fade: f32,
 }
 
 pub struct WgpuOutput {
@@ -107,15 +124,46 @@ impl WgpuContext {
                 params.inv_cell_x,
                 params.inv_cell_y,
                 params.randomness,
-                params.lp_exp,
+                params.lp_exp[0],
+            ],
+            extra: [
+                params.inv_cell_w,
+                params.wave_amount,
+                params.wave_frequency,
+                params.wave_angle,
             ],
-            extra: [params.inv_cell_w, 0.0, 0.0, 0.0],
             misc: [
                 params.smoothness,
                 params.w_value,
                 params.offset_x,
                 params.offset_y,
             ],
+            flow: [params.flow_time, params.flow_turbulence, 0.0, 0.0],
+            jitter: [
+                params.jitter_time,
+                params.jitter,
+                params.jitter_frequency,
+                0.0,
+            ],
+            lp: [
+                params.lp_exp[1],
+                params.lp_exp[2],
+                params.cylinder_radius,
+                0.0,
+            ],
+            edge: [
+                params.edge_thickness,
+                params.edge_softness,
+                params.stipple_radius,
+                params.stipple_softness,
+            ],
+            uv: [
+                params.cell_uv_scale,
+                params.cell_uv_offset.0,
+                params.cell_uv_offset.1,
+                0.0,
+            ],
+            reveal: [params.reveal_progress, params.reveal_fade, 0.0, 0.0],
         };
         self.queue
             .write_buffer(&res.params_buf, 0, bytemuck::bytes_of(&param_buf));
@@ -230,6 +278,12 @@ struct Params {
     cell: [f32; 4],
     extra: [f32; 4],
     misc: [f32; 4],
+    flow: [f32; 4],
+    jitter: [f32; 4],
+    lp: [f32; 4],
+    edge: [f32; 4],
+    uv: [f32; 4],
+    reveal: [f32; 4],
 }
 
 fn create_pipeline(device: &Device) -> Result<(ComputePipeline, BindGroupLayout), ae::Error> {
@@ -4,6 +4,12 @@ struct Params {
     cell: vec4<f32>,
     extra: vec4<f32>,
     misc: vec4<f32>,
+    flow: vec4<f32>,
+    jitter: vec4<f32>,
+    lp: vec4<f32>,
+    edge: vec4<f32>,
+    uv: vec4<f32>,
+    reveal: vec4<f32>,
 };
 
 @group(0) @binding(0) var<uniform> params: Params;
@@ -40,13 +46,38 @@ fn rand01(h: u32) -> f32 {
     return f32(h) / 4294967295.0;
 }
 
+fn flow_offset(h: u32, flow_time: f32, turbulence: f32) -> vec2<f32> {
+    if turbulence <= 0.0 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let tau = 6.28318530718;
+    let phase_x = rand01(hash_u32(h ^ 0x2C1B3C6Du)) * tau;
+    let phase_y = rand01(hash_u32(h ^ 0x297A2D39u)) * tau;
+    let rate = 0.5 + rand01(hash_u32(h ^ 0x5851F42Du));
+    let t = flow_time * rate * tau;
+    let amp = 0.5 * turbulence;
+    return vec2<f32>(sin(t + phase_x) * amp, cos(t + phase_y) * amp);
+}
+
+fn jitter_offset(h: u32) -> vec2<f32> {
+    if params.jitter.y <= 0.0 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let phase = rand01(hash_u32(h ^ 0x7F4A7C15u)) * 6.28318530718;
+    let t = params.jitter.x * params.jitter.z + phase;
+    let amp = 0.5 * params.jitter.y;
+    return vec2<f32>(sin(t) * amp, cos(t) * amp);
+}
+
 fn cell_point(cell_x: i32, cell_y: i32, cell_w: i32, randomness: f32, seed: u32) -> Site {
     let h = hash3(cell_x, cell_y, cell_w, seed);
     let rx = rand01(hash_u32(h ^ 0xA511E9B3u));
     let ry = rand01(hash_u32(h ^ 0x63D83595u));
     let rw = rand01(hash_u32(h ^ 0x1F1D8E33u));
-    let ox = 0.5 + (rx - 0.5) * randomness;
-    let oy = 0.5 + (ry - 0.5) * randomness;
+    let flow = flow_offset(h, params.flow.x, params.flow.y);
+    let jitter = jitter_offset(h);
+    let ox = clamp(0.5 + (rx - 0.5) * randomness + flow.x + jitter.x, 0.0, 1.0);
+    let oy = clamp(0.5 + (ry - 0.5) * randomness + flow.y + jitter.y, 0.0, 1.0);
     let ow = 0.5 + (rw - 0.5) * randomness;
     return Site(f32(cell_x) + ox, f32(cell_y) + oy, f32(cell_w) + ow, h);
 }
@@ -58,7 +89,7 @@ fn hash_color(h: u32) -> vec3<f32> {
     return vec3<f32>(r, g, b);
 }
 
-fn metric_distance(dx: f32, dy: f32, dw: f32, metric: u32, lp_exp: f32) -> f32 {
+fn metric_distance(dx: f32, dy: f32, dw: f32, metric: u32, lp_exp: vec3<f32>) -> f32 {
     let adx = abs(dx);
     let ady = abs(dy);
     let adw = abs(dw);
@@ -71,11 +102,115 @@ fn metric_distance(dx: f32, dy: f32, dw: f32, metric: u32, lp_exp: f32) -> f32 {
     if metric == 2u {
         return max(max(adx, ady), adw);
     }
-    let p = max(lp_exp, 0.1);
-    let s = pow(adx, p) + pow(ady, p) + pow(adw, p);
+    if metric == 4u {
+        return sqrt(dx * dx + dy * dy) - params.lp.z * (1.0 - min(adw, 1.0));
+    }
+    let pe = max(lp_exp, vec3<f32>(0.1));
+    let p = (pe.x + pe.y + pe.z) / 3.0;
+    let s = pow(adx, pe.x) + pow(ady, pe.y) + pow(adw, pe.z);
     return pow(s, 1.0 / p);
 }
 
+fn wave_offset(px: f32, py: f32, amount: f32, frequency: f32, angle: f32) -> vec2<f32> {
+    if amount <= 0.0 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let tau = 6.28318530718;
+    let wx = sin(py * frequency * tau) * amount;
+    let wy = cos(px * frequency * tau) * amount;
+    let s = sin(angle);
+    let c = cos(angle);
+    return vec2<f32>(wx * c - wy * s, wx * s + wy * c);
+}
+
+fn segment_distance(px: f32, py: f32, a: Site, b: Site) -> f32 {
+    let abx = b.x - a.x;
+    let aby = b.y - a.y;
+    let len2 = abx * abx + aby * aby;
+    var t = 0.0;
+    if (len2 > 0.0) {
+        t = clamp(((px - a.x) * abx + (py - a.y) * aby) / len2, 0.0, 1.0);
+    }
+    let dx = px - (a.x + abx * t);
+    let dy = py - (a.y + aby * t);
+    return sqrt(dx * dx + dy * dy);
+}
+
+fn delaunay_edge_value(px: f32, py: f32, pw: f32, lp_exp: vec3<f32>) -> f32 {
+    let cell_x = i32(floor(px));
+    let cell_y = i32(floor(py));
+    let cell_w = i32(floor(pw));
+    var d0 = 1e20;
+    var d1 = 1e20;
+    var d2 = 1e20;
+    var s0 = Site(0.0, 0.0, 0.0, 0u);
+    var s1 = Site(0.0, 0.0, 0.0, 0u);
+    var s2 = Site(0.0, 0.0, 0.0, 0u);
+
+    for (var nw: i32 = cell_w - 1; nw <= cell_w + 1; nw = nw + 1) {
+        for (var ny: i32 = cell_y - 2; ny <= cell_y + 2; ny = ny + 1) {
+            for (var nx: i32 = cell_x - 2; nx <= cell_x + 2; nx = nx + 1) {
+                let site = cell_point(nx, ny, nw, params.cell.z, params.seed.x);
+                let d = metric_distance(px - site.x, py - site.y, pw - site.w, params.size.z, lp_exp);
+                if (d < d0) {
+                    d2 = d1;
+                    s2 = s1;
+                    d1 = d0;
+                    s1 = s0;
+                    d0 = d;
+                    s0 = site;
+                } else if (d < d1) {
+                    d2 = d1;
+                    s2 = s1;
+                    d1 = d;
+                    s1 = site;
+                } else if (d < d2) {
+                    d2 = d;
+                    s2 = site;
+                }
+            }
+        }
+    }
+
+    let d = min(
+        segment_distance(px, py, s0, s1),
+        min(segment_distance(px, py, s0, s2), segment_distance(px, py, s1, s2))
+    );
+    let thickness = params.edge.x;
+    let softness = params.edge.y;
+    if (softness <= 0.0) {
+        return select(0.0, 1.0, d <= thickness);
+    }
+    return 1.0 - smoothstep01((d - thickness) / softness);
+}
+
+fn stipple_coverage(dx: f32, dy: f32) -> f32 {
+    let d = sqrt(dx * dx + dy * dy);
+    let radius = params.edge.z;
+    let softness = params.edge.w;
+    if (softness <= 0.0) {
+        return select(0.0, 1.0, d <= radius);
+    }
+    return 1.0 - smoothstep01((d - radius) / softness);
+}
+
+fn cell_uv(dx: f32, dy: f32, d2: f32) -> vec2<f32> {
+    let radius = max(d2 * 0.5, 1e-6);
+    let k = 0.5 * params.uv.x / radius;
+    let uv = vec2<f32>(0.5 + (dx - params.uv.y) * k, 0.5 + (dy - params.uv.z) * k);
+    return clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0));
+}
+
+fn cell_reveal(h: u32) -> f32 {
+    let r = rand01(hash_u32(h ^ 0x4F6CDD1Du));
+    let progress = params.reveal.x;
+    let fade = params.reveal.y;
+    if (fade <= 0.0) {
+        return select(0.0, 1.0, r < progress || progress >= 1.0);
+    }
+    return clamp((progress * (1.0 + fade) - r) / fade, 0.0, 1.0);
+}
+
 fn smoothstep01(x: f32) -> f32 {
     let t = clamp(x, 0.0, 1.0);
     return t * t * (3.0 - 2.0 * t);
@@ -104,15 +239,18 @@ fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
     let inv_cell_x = params.cell.x;
     let inv_cell_y = params.cell.y;
     let randomness = params.cell.z;
-    let lp_exp = params.cell.w;
+    let lp_exp = vec3<f32>(params.cell.w, params.lp.x, params.lp.y);
     let inv_cell_w = params.extra.x;
     let smoothness = params.misc.x;
     let w_value = params.misc.y;
     let offset_x = params.misc.z;
     let offset_y = params.misc.w;
 
-    let px = (f32(gid.x) + 0.5 - offset_x) * inv_cell_x;
-    let py = (f32(gid.y) + 0.5 - offset_y) * inv_cell_y;
+    let base_x = (f32(gid.x) + 0.5 - offset_x) * inv_cell_x;
+    let base_y = (f32(gid.y) + 0.5 - offset_y) * inv_cell_y;
+    let wave = wave_offset(base_x, base_y, params.extra.y, params.extra.z, params.extra.w);
+    let px = base_x + wave.x;
+    let py = base_y + wave.y;
     let pw = w_value * inv_cell_w;
     let cell_x = i32(floor(px));
     let cell_y = i32(floor(py));
@@ -175,6 +313,16 @@ fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
     } else if (params.size.w == 3u) {
         let v = d1;
         out = vec4<f32>(v, v, v, 1.0);
+    } else if (params.size.w == 6u) {
+        let v = delaunay_edge_value(px, py, pw, lp_exp);
+        out = vec4<f32>(v, v, v, 1.0);
+    } else if (params.size.w == 7u) {
+        out = vec4<f32>(hash_color(nearest.hash), stipple_coverage(px - nearest.x, py - nearest.y));
+    } else if (params.size.w == 8u) {
+        out = vec4<f32>(cell_uv(px - nearest.x, py - nearest.y, d2), 0.0, 1.0);
+    } else if (params.size.w == 9u) {
+        let v = cell_reveal(nearest.hash);
+        out = vec4<f32>(v, v, v, 1.0);
     } else {
         let v = max(d2 - d1, 0.0);
         out = vec4<f32>(v, v, v, 1.0);
@@ -3,11 +3,13 @@
 use after_effects as ae;
 use std::env;
 
+use std::sync::{Arc, Mutex};
+
 #[cfg(feature = "gpu_wgpu")]
-use std::sync::{Arc, OnceLock};
+use std::sync::OnceLock;
 
 use ae::pf::*;
-use utils::ToPixel;
+use utils::{ToPixel, hash_u32, rand01};
 
 #[cfg(feature = "gpu_wgpu")]
 mod gpu;
@@ -35,6 +37,56 @@ enum Params {
     Offset,
     Clamp32,
     UseOriginalAlpha,
+    CellSpacingGroupStart,
+    CellSpacingGroupEnd,
+    DistanceOptionsGroupStart,
+    DistanceOptionsGroupEnd,
+    DistortionGroupStart,
+    DistortionGroupEnd,
+    FlowGroupStart,
+    FlowGroupEnd,
+    OutputOptionsGroupStart,
+    OutputOptionsGroupEnd,
+    MinSiteDistance,
+    RepulsionIters,
+    LpFollowX,
+    LpExponentY,
+    LpExponentW,
+    CylinderRadius,
+    WMapLayer,
+    WMapAmount,
+    WaveAmount,
+    WaveFrequency,
+    WaveAngle,
+    WarpNoise,
+    WarpNoiseScale,
+    FlowSpeed,
+    FlowTurbulence,
+    TimeJitter,
+    JitterFrequency,
+    CellFill,
+    EdgeThickness,
+    EdgeSoftness,
+    PositionEncoding,
+    FeatureFormula,
+    AutoLevels,
+    TemporalSmoothing,
+    OctaveCount,
+    OctaveLacunarity,
+    OctaveGain,
+    StippleRadius,
+    StippleSoftness,
+    StippleColor,
+    StippleSiteColor,
+    EdgeWidth,
+    EdgeColor,
+    EdgeAlpha,
+    CellUVScale,
+    CellUVOffset,
+    RevealProgress,
+    RevealFade,
+    LegacyOrigin,
+    ExportCells,
 }
 
 #[derive(Clone, Copy)]
@@ -43,6 +95,14 @@ enum DistanceMetric {
     Manhattan,
     Chebyshev,
     Lp,
+    Cylindrical(f32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CellFill {
+    Flat,
+    Radial,
+    Linear,
 }
 
 #[derive(Clone, Copy)]
@@ -52,6 +112,67 @@ enum OutputType {
     F,
     Distance,
     Edge,
+    BoundaryBlend,
+    Delaunay,
+    Stipple,
+    Mesh,
+    CellUV,
+    Reveal,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FeatureFormula {
+    F1,
+    F2,
+    F3,
+    F2MinusF1,
+    F3MinusF1,
+    MeanF1F2,
+    RatioF1F2,
+}
+
+impl FeatureFormula {
+    fn from_popup(value: i32) -> Self {
+        match value {
+            2 => Self::F2,
+            3 => Self::F3,
+            4 => Self::F2MinusF1,
+            5 => Self::F3MinusF1,
+            6 => Self::MeanF1F2,
+            7 => Self::RatioF1F2,
+            _ => Self::F1,
+        }
+    }
+
+    fn uses_f3(self) -> bool {
+        matches!(self, Self::F3 | Self::F3MinusF1)
+    }
+
+    fn eval(self, sample: &SiteSample) -> f32 {
+        let SiteSample { d1, d2, d3, .. } = *sample;
+        match self {
+            Self::F1 => d1,
+            Self::F2 => d2,
+            Self::F3 => d3,
+            Self::F2MinusF1 => d2 - d1,
+            Self::F3MinusF1 => d3 - d1,
+            Self::MeanF1F2 => (d1 + d2) * 0.5,
+            Self::RatioF1F2 => {
+                if d2 > 1.0e-6 {
+                    d1 / d2
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum PositionEncoding {
+    Normalized,
+    Centered,
+    Raw,
 }
 
 #[derive(Clone, Copy, Default)]
@@ -63,9 +184,34 @@ struct Site {
 }
 
 #[derive(Default)]
-struct Plugin {}
+struct Plugin {
+    aegp_id: Option<ae::aegp::PluginId>,
+}
+
+// Per-instance state kept in sequence data. The levels history only lives for
+// the session, so only the format version is flattened into the project.
+struct Instance {
+    levels: Mutex<LevelsHistory>,
+    // Set for instances from projects saved before Legacy Origin existed: they
+    // render with the old origin until the checkbox has been ticked for them.
+    legacy_origin_pending: bool,
+}
+
+// Sequence data version; anything older predates Legacy Origin.
+const INSTANCE_VERSION: u16 = 2;
 
-ae::define_effect!(Plugin, (), Params);
+// An instance that is neither freshly applied (SequenceSetup) nor read back from
+// current sequence data comes from an old project.
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            levels: Mutex::default(),
+            legacy_origin_pending: true,
+        }
+    }
+}
+
+ae::define_effect!(Plugin, Instance, Params);
 
 const PLUGIN_DESCRIPTION: &str = "Generates Voronoi texture maps";
 
@@ -80,6 +226,119 @@ fn wgpu_context() -> Option<Arc<WgpuContext>> {
     }
 }
 
+impl AdobePluginInstance for Instance {
+    fn flatten(&self) -> Result<(u16, Vec<u8>), Error> {
+        // Flattened while still pending, the next load must migrate again.
+        let version = if self.legacy_origin_pending {
+            INSTANCE_VERSION - 1
+        } else {
+            INSTANCE_VERSION
+        };
+        Ok((version, Vec::new()))
+    }
+
+    fn unflatten(version: u16, _serialized: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            legacy_origin_pending: version < INSTANCE_VERSION,
+            ..Self::default()
+        })
+    }
+
+    fn render(&self, _: &mut PluginState, _: &Layer, _: &mut Layer) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn do_dialog(&mut self, _: &mut PluginState) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Rendering lives here so Temporal Smoothing reads this instance's history.
+    fn handle_command(&mut self, plugin: &mut PluginState, cmd: ae::Command) -> Result<(), Error> {
+        match cmd {
+            ae::Command::SequenceSetup => {
+                self.legacy_origin_pending = false;
+            }
+            // Param values may only change here, so an old project's instance gets
+            // Legacy Origin ticked on its first edit. Touching the checkbox itself
+            // is an explicit choice and is kept.
+            ae::Command::UserChangedParam { param_index } if self.legacy_origin_pending => {
+                if plugin.params.type_at(param_index) != Params::LegacyOrigin {
+                    let mut p = plugin.params.get_mut(Params::LegacyOrigin)?;
+                    p.as_checkbox_mut()?.set_value(true);
+                    p.set_value_changed();
+                }
+                self.legacy_origin_pending = false;
+            }
+            ae::Command::Render {
+                in_layer,
+                mut out_layer,
+            } => {
+                #[cfg(feature = "gpu_wgpu")]
+                {
+                    if let Some(ctx) = wgpu_context()
+                        && plugin
+                            .global
+                            .do_render_wgpu(
+                                plugin.in_data,
+                                &in_layer,
+                                &mut out_layer,
+                                plugin.params,
+                                &ctx,
+                            )
+                            .is_ok()
+                    {
+                        return Ok(());
+                    }
+                }
+                plugin.global.do_render(
+                    plugin.in_data,
+                    in_layer,
+                    out_layer,
+                    plugin.params,
+                    BufferOrigin::default(),
+                    &self.levels,
+                )?;
+            }
+
+            ae::Command::SmartRender { extra } => {
+                let cb = extra.callbacks();
+                let in_layer_opt = cb.checkout_layer_pixels(0)?;
+                let out_layer_opt = cb.checkout_output()?;
+
+                let origin = if self.legacy_origin_pending
+                    || plugin
+                        .params
+                        .get(Params::LegacyOrigin)?
+                        .as_checkbox()?
+                        .value()
+                {
+                    BufferOrigin::default()
+                } else {
+                    extra
+                        .pre_render_data::<BufferOrigin>()
+                        .copied()
+                        .unwrap_or_default()
+                };
+
+                if let (Some(in_layer), Some(out_layer)) = (in_layer_opt, out_layer_opt) {
+                    plugin.global.do_render(
+                        plugin.in_data,
+                        in_layer,
+                        out_layer,
+                        plugin.params,
+                        origin,
+                        &self.levels,
+                    )?;
+                }
+
+                cb.checkin_layer_pixels(0)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
 impl AdobePluginGlobal for Plugin {
     fn params_setup(
         &self,
@@ -167,13 +426,21 @@ impl AdobePluginGlobal for Plugin {
             "Distance",
             false,
             |params| {
-                params.add(
+                params.add_with_flags(
                     Params::DistanceMetric,
                     "Distance Metric",
                     PopupDef::setup(|d| {
-                        d.set_options(&["Euclidean", "Manhattan", "Chebyshev", "Lp"]);
+                        d.set_options(&[
+                            "Euclidean",
+                            "Manhattan",
+                            "Chebyshev",
+                            "Lp",
+                            "Cylindrical",
+                        ]);
                         d.set_default(1);
                     }),
+                    ae::ParamFlag::SUPERVISE,
+                    ae::ParamUIFlags::empty(),
                 )?;
 
                 params.add(
@@ -238,7 +505,7 @@ impl AdobePluginGlobal for Plugin {
             "Output",
             false,
             |params| {
-                params.add(
+                params.add_with_flags(
                     Params::OutputType,
                     "Output",
                     PopupDef::setup(|d| {
@@ -248,9 +515,17 @@ impl AdobePluginGlobal for Plugin {
                             "F (Smooth F1)",
                             "Distance (F1)",
                             "Edge (F2 - F1)",
+                            "Segment Boundaries",
+                            "Delaunay Triangulation",
+                            "Stippling",
+                            "Mesh Triangulation",
+                            "Cell UV",
+                            "Reveal",
                         ]);
                         d.set_default(1);
                     }),
+                    ae::ParamFlag::SUPERVISE,
+                    ae::ParamUIFlags::empty(),
                 )?;
 
                 params.add(
@@ -281,136 +556,762 @@ impl AdobePluginGlobal for Plugin {
             },
         )?;
 
-        Ok(())
-    }
-
-    fn handle_command(
-        &mut self,
-        cmd: ae::Command,
-        in_data: InData,
-        mut out_data: OutData,
-        params: &mut ae::Parameters<Params>,
-    ) -> Result<(), ae::Error> {
-        match cmd {
-            ae::Command::About => {
-                out_data.set_return_msg(
-                    format!(
-                        "AOD_VoronoiGenerate - {version}\r\r{PLUGIN_DESCRIPTION}\rCopyright (c) 2026-{build_year} Aodaruma",
-                        version = env!("CARGO_PKG_VERSION"),
-                        build_year = env!("BUILD_YEAR")
-                    )
-                    .as_str(),
-                );
-            }
-            ae::Command::GlobalSetup => {
-                // Declare that we do or do not support smart rendering
-                out_data.set_out_flag2(OutFlags2::SupportsSmartRender, true);
-            }
-            ae::Command::Render {
-                in_layer,
-                mut out_layer,
-            } => {
-                #[cfg(feature = "gpu_wgpu")]
-                {
-                    if let Some(ctx) = wgpu_context()
-                        && self
-                            .do_render_wgpu(in_data, &in_layer, &mut out_layer, params, &ctx)
-                            .is_ok()
-                    {
-                        return Ok(());
-                    }
-                }
-                self.do_render(in_data, in_layer, out_data, out_layer, params)?;
-            }
+        params.add_group(
+            Params::CellSpacingGroupStart,
+            Params::CellSpacingGroupEnd,
+            "Cell Spacing",
+            false,
+            |params| {
+                params.add(
+                    Params::MinSiteDistance,
+                    "Min Site Distance",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
 
-            ae::Command::SmartPreRender { mut extra } => {
-                let req = extra.output_request();
+                params.add(
+                    Params::RepulsionIters,
+                    "Repulsion Iterations",
+                    SliderDef::setup(|d| {
+                        d.set_valid_min(1);
+                        d.set_valid_max(10);
+                        d.set_slider_min(1);
+                        d.set_slider_max(10);
+                        d.set_default(3);
+                    }),
+                )?;
 
-                if let Ok(in_result) = extra.callbacks().checkout_layer(
-                    0,
-                    0,
-                    &req,
-                    in_data.current_time(),
-                    in_data.time_step(),
-                    in_data.time_scale(),
-                ) {
-                    let _ = extra.union_result_rect(in_result.result_rect.into());
-                    let _ = extra.union_max_result_rect(in_result.max_result_rect.into());
-                } else {
-                    return Err(Error::InterruptCancel);
-                }
-            }
+                Ok(())
+            },
+        )?;
 
-            ae::Command::SmartRender { extra } => {
-                let cb = extra.callbacks();
-                let in_layer_opt = cb.checkout_layer_pixels(0)?;
-                let out_layer_opt = cb.checkout_output()?;
+        params.add_group(
+            Params::DistanceOptionsGroupStart,
+            Params::DistanceOptionsGroupEnd,
+            "Distance Options",
+            false,
+            |params| {
+                params.add_with_flags(
+                    Params::LpFollowX,
+                    "Lp Y/W Follow X",
+                    CheckBoxDef::setup(|d| {
+                        d.set_default(true);
+                    }),
+                    ae::ParamFlag::SUPERVISE,
+                    ae::ParamUIFlags::empty(),
+                )?;
 
-                if let (Some(in_layer), Some(out_layer)) = (in_layer_opt, out_layer_opt) {
-                    self.do_render(in_data, in_layer, out_data, out_layer, params)?;
-                }
+                params.add(
+                    Params::LpExponentY,
+                    "Lp Exponent Y",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.1);
+                        d.set_valid_max(16.0);
+                        d.set_slider_min(0.5);
+                        d.set_slider_max(8.0);
+                        d.set_default(2.0);
+                        d.set_precision(2);
+                    }),
+                )?;
 
-                cb.checkin_layer_pixels(0)?;
-            }
-            ae::Command::UserChangedParam { param_index } => {
-                if params.type_at(param_index) == Params::DistanceMetric {
-                    out_data.set_out_flag(OutFlags::RefreshUi, true);
-                }
-            }
-            ae::Command::UpdateParamsUi => {
-                let mut params_copy = params.cloned();
-                Self::update_params_ui(&mut params_copy)?;
-            }
-            _ => {}
-        }
-        Ok(())
-    }
-}
+                params.add(
+                    Params::LpExponentW,
+                    "Lp Exponent W",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.1);
+                        d.set_valid_max(16.0);
+                        d.set_slider_min(0.5);
+                        d.set_slider_max(8.0);
+                        d.set_default(2.0);
+                        d.set_precision(2);
+                    }),
+                )?;
 
-impl Plugin {
-    fn update_params_ui(params: &mut Parameters<Params>) -> Result<(), Error> {
-        let metric = params.get(Params::DistanceMetric)?.as_popup()?.value();
-        let is_lp = metric == 4;
-        Self::set_param_enabled(params, Params::LpExponent, is_lp)?;
+                params.add(
+                    Params::CylinderRadius,
+                    "Cylinder Radius",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.25);
+                        d.set_precision(3);
+                    }),
+                )?;
 
-        Ok(())
-    }
+                params.add(Params::WMapLayer, "W Map Layer", LayerDef::new())?;
 
-    fn set_param_enabled(
-        params: &mut ae::Parameters<Params>,
-        id: Params,
-        enabled: bool,
-    ) -> Result<(), Error> {
-        Self::set_param_ui_flag(params, id, ae::pf::ParamUIFlags::DISABLED, !enabled)
-    }
+                params.add(
+                    Params::WMapAmount,
+                    "W Map Amount",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(-50000.0);
+                        d.set_valid_max(50000.0);
+                        d.set_slider_min(-1.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
 
-    fn set_param_ui_flag(
-        params: &mut ae::Parameters<Params>,
-        id: Params,
-        flag: ae::pf::ParamUIFlags,
-        status: bool,
-    ) -> Result<(), Error> {
-        let mut p = params.get_mut(id)?;
-        p.set_ui_flag(flag, status);
-        p.update_param_ui()?;
-        Ok(())
-    }
+                Ok(())
+            },
+        )?;
 
-    #[cfg(feature = "gpu_wgpu")]
-    fn do_render_wgpu(
-        &self,
-        _in_data: InData,
-        in_layer: &Layer,
-        out_layer: &mut Layer,
-        params: &mut Parameters<Params>,
-        ctx: &WgpuContext,
-    ) -> Result<(), Error> {
+        params.add_group(
+            Params::DistortionGroupStart,
+            Params::DistortionGroupEnd,
+            "Distortion",
+            false,
+            |params| {
+                params.add(
+                    Params::WaveAmount,
+                    "Wave Amount",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::WaveFrequency,
+                    "Wave Frequency",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(10.0);
+                        d.set_default(1.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::WaveAngle,
+                    "Wave Angle (deg)",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(-3600.0);
+                        d.set_valid_max(3600.0);
+                        d.set_slider_min(-180.0);
+                        d.set_slider_max(180.0);
+                        d.set_default(0.0);
+                        d.set_precision(1);
+                    }),
+                )?;
+
+                params.add(
+                    Params::WarpNoise,
+                    "Noise Warp",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::WarpNoiseScale,
+                    "Noise Warp Scale",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(10.0);
+                        d.set_default(1.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                Ok(())
+            },
+        )?;
+
+        params.add_group(
+            Params::FlowGroupStart,
+            Params::FlowGroupEnd,
+            "Flow",
+            false,
+            |params| {
+                params.add(
+                    Params::FlowSpeed,
+                    "Flow Speed",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(100.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(5.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::FlowTurbulence,
+                    "Flow Turbulence",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.5);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::TimeJitter,
+                    "Jitter over Time",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::JitterFrequency,
+                    "Jitter Frequency",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(10.0);
+                        d.set_default(1.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                Ok(())
+            },
+        )?;
+
+        params.add_group(
+            Params::OutputOptionsGroupStart,
+            Params::OutputOptionsGroupEnd,
+            "Output Options",
+            false,
+            |params| {
+                params.add(
+                    Params::CellFill,
+                    "Cell Fill",
+                    PopupDef::setup(|d| {
+                        d.set_options(&["Flat", "Radial Gradient", "Linear Gradient"]);
+                        d.set_default(1);
+                    }),
+                )?;
+
+                params.add(
+                    Params::EdgeThickness,
+                    "Edge Thickness",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.05);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::EdgeSoftness,
+                    "Edge Softness",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.02);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::PositionEncoding,
+                    "Position Encoding",
+                    PopupDef::setup(|d| {
+                        d.set_options(&[
+                            "Normalized (0..1)",
+                            "Centered (-0.5..0.5)",
+                            "Raw Pixels (32bpc)",
+                        ]);
+                        d.set_default(1);
+                    }),
+                )?;
+
+                params.add(
+                    Params::FeatureFormula,
+                    "Feature Formula",
+                    PopupDef::setup(|d| {
+                        d.set_options(&[
+                            "F1",
+                            "F2",
+                            "F3",
+                            "F2 - F1",
+                            "F3 - F1",
+                            "(F1 + F2) / 2",
+                            "F1 / F2",
+                        ]);
+                        d.set_default(1);
+                    }),
+                )?;
+
+                params.add(
+                    Params::AutoLevels,
+                    "Auto Levels",
+                    CheckBoxDef::setup(|d| {
+                        d.set_default(false);
+                    }),
+                )?;
+
+                params.add(
+                    Params::TemporalSmoothing,
+                    "Temporal Smoothing",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::OctaveCount,
+                    "Octaves",
+                    SliderDef::setup(|d| {
+                        d.set_valid_min(1);
+                        d.set_valid_max(8);
+                        d.set_slider_min(1);
+                        d.set_slider_max(8);
+                        d.set_default(1);
+                    }),
+                )?;
+
+                params.add(
+                    Params::OctaveLacunarity,
+                    "Octave Lacunarity",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(1.0);
+                        d.set_valid_max(4.0);
+                        d.set_slider_min(1.0);
+                        d.set_slider_max(4.0);
+                        d.set_default(2.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::OctaveGain,
+                    "Octave Gain",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.5);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::StippleRadius,
+                    "Stipple Radius",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.15);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::StippleSoftness,
+                    "Stipple Softness",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(10.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.02);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::StippleColor,
+                    "Stipple Color",
+                    ColorDef::setup(|d| {
+                        d.set_default(Pixel8 {
+                            red: 255,
+                            green: 255,
+                            blue: 255,
+                            alpha: 255,
+                        });
+                    }),
+                )?;
+
+                params.add(
+                    Params::StippleSiteColor,
+                    "Stipple Site Colors",
+                    CheckBoxDef::setup(|d| {
+                        d.set_default(true);
+                    }),
+                )?;
+
+                params.add(
+                    Params::EdgeWidth,
+                    "Mesh Edge Width",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.5);
+                        d.set_valid_max(8.0);
+                        d.set_slider_min(0.5);
+                        d.set_slider_max(8.0);
+                        d.set_default(1.5);
+                        d.set_precision(2);
+                    }),
+                )?;
+
+                params.add(
+                    Params::EdgeColor,
+                    "Mesh Edge Color",
+                    ColorDef::setup(|d| {
+                        d.set_default(Pixel8 {
+                            red: 255,
+                            green: 255,
+                            blue: 255,
+                            alpha: 255,
+                        });
+                    }),
+                )?;
+
+                params.add(
+                    Params::EdgeAlpha,
+                    "Mesh Edge Alpha",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(1.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::CellUVScale,
+                    "Cell UV Scale",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(4.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(4.0);
+                        d.set_default(1.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::CellUVOffset,
+                    "Cell UV Offset",
+                    PointDef::setup(|p| {
+                        p.set_default((0.0, 0.0));
+                    }),
+                )?;
+
+                params.add(
+                    Params::RevealProgress,
+                    "Reveal Progress",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(1.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                params.add(
+                    Params::RevealFade,
+                    "Reveal Fade",
+                    FloatSliderDef::setup(|d| {
+                        d.set_valid_min(0.0);
+                        d.set_valid_max(1.0);
+                        d.set_slider_min(0.0);
+                        d.set_slider_max(1.0);
+                        d.set_default(0.0);
+                        d.set_precision(3);
+                    }),
+                )?;
+
+                // Projects saved before this param existed are migrated to it by
+                // Instance; new instances pin the lattice to the layer.
+                params.add(
+                    Params::LegacyOrigin,
+                    "Legacy Origin",
+                    CheckBoxDef::setup(|d| {
+                        d.set_default(false);
+                    }),
+                )?;
+
+                params.add_with_flags(
+                    Params::ExportCells,
+                    "Export Cells",
+                    ButtonDef::setup(|d| {
+                        d.set_label("Export...");
+                    }),
+                    ae::ParamFlag::SUPERVISE,
+                    ae::ParamUIFlags::empty(),
+                )?;
+
+                Ok(())
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn handle_command(
+        &mut self,
+        cmd: ae::Command,
+        in_data: InData,
+        mut out_data: OutData,
+        params: &mut ae::Parameters<Params>,
+    ) -> Result<(), ae::Error> {
+        match cmd {
+            ae::Command::About => {
+                out_data.set_return_msg(
+                    format!(
+                        "AOD_VoronoiGenerate - {version}\r\r{PLUGIN_DESCRIPTION}\rCopyright (c) 2026-{build_year} Aodaruma",
+                        version = env!("CARGO_PKG_VERSION"),
+                        build_year = env!("BUILD_YEAR")
+                    )
+                    .as_str(),
+                );
+            }
+            ae::Command::GlobalSetup => {
+                out_data.set_out_flag(OutFlags::SendUpdateParamsUi, true);
+                // Declare that we do or do not support smart rendering
+                out_data.set_out_flag2(OutFlags2::SupportsSmartRender, true);
+                if let Ok(suite) = ae::aegp::suites::Utility::new()
+                    && let Ok(plugin_id) = suite.register_with_aegp("AOD_VoronoiGenerate")
+                {
+                    self.aegp_id = Some(plugin_id);
+                }
+            }
+            ae::Command::SmartPreRender { mut extra } => {
+                let req = extra.output_request();
+
+                if let Ok(in_result) = extra.callbacks().checkout_layer(
+                    0,
+                    0,
+                    &req,
+                    in_data.current_time(),
+                    in_data.time_step(),
+                    in_data.time_scale(),
+                ) {
+                    // The input rect is where the output buffer starts in layer space.
+                    // Stashed here because in_data's origins are not reliable in
+                    // SmartRender for collapsed precomps.
+                    let rect = in_result.result_rect;
+                    extra.set_pre_render_data(BufferOrigin(rect.left, rect.top));
+                    let _ = extra.union_result_rect(in_result.result_rect.into());
+                    let _ = extra.union_max_result_rect(in_result.max_result_rect.into());
+                } else {
+                    return Err(Error::InterruptCancel);
+                }
+            }
+
+            ae::Command::UserChangedParam { param_index } => {
+                let t = params.type_at(param_index);
+                if t == Params::DistanceMetric || t == Params::LpFollowX || t == Params::OutputType
+                {
+                    out_data.set_out_flag(OutFlags::RefreshUi, true);
+                }
+                if t == Params::FlowSpeed || t == Params::FlowTurbulence || t == Params::TimeJitter
+                {
+                    out_data.set_out_flag(OutFlags::NonParamVary, varies_per_frame(params)?);
+                }
+                // Export problems are reported here and never reach rendering.
+                if t == Params::ExportCells {
+                    match export_cells(in_data, params) {
+                        Ok(Some((count, path))) => out_data.set_return_msg(&format!(
+                            "Exported {count} cells to {}",
+                            path.display()
+                        )),
+                        Ok(None) => {}
+                        Err(err) => out_data.set_return_msg(&format!("Export Cells failed: {err}")),
+                    }
+                }
+            }
+            ae::Command::UpdateParamsUi => {
+                out_data.set_out_flag(OutFlags::NonParamVary, varies_per_frame(params)?);
+                let mut params_copy = params.cloned();
+                self.update_params_ui(in_data, &mut params_copy)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Plugin {
+    fn update_params_ui(
+        &self,
+        in_data: InData,
+        params: &mut Parameters<Params>,
+    ) -> Result<(), Error> {
+        let metric = params.get(Params::DistanceMetric)?.as_popup()?.value();
+        let is_lp = metric == 4;
+        let is_cylindrical = metric == 5;
+        let follow_x = params.get(Params::LpFollowX)?.as_checkbox()?.value();
+        Self::set_param_enabled(params, Params::LpExponent, is_lp)?;
+        Self::set_param_enabled(params, Params::LpFollowX, is_lp)?;
+        Self::set_param_enabled(params, Params::LpExponentY, is_lp && !follow_x)?;
+        Self::set_param_enabled(params, Params::LpExponentW, is_lp && !follow_x)?;
+        self.set_param_visible(in_data, params, Params::CylinderRadius, is_cylindrical)?;
+
+        let output_type = params.get(Params::OutputType)?.as_popup()?.value();
+        Self::set_param_enabled(params, Params::CellFill, output_type == 1)?;
+        Self::set_param_enabled(params, Params::FeatureFormula, output_type == 4)?;
+        for id in [
+            Params::OctaveCount,
+            Params::OctaveLacunarity,
+            Params::OctaveGain,
+        ] {
+            Self::set_param_enabled(params, id, output_type == 4)?;
+        }
+        Self::set_param_enabled(params, Params::CellUVScale, output_type == 10)?;
+        Self::set_param_enabled(params, Params::CellUVOffset, output_type == 10)?;
+        Self::set_param_enabled(params, Params::RevealProgress, output_type == 11)?;
+        Self::set_param_enabled(params, Params::RevealFade, output_type == 11)?;
+
+        Ok(())
+    }
+
+    fn set_param_visible(
+        &self,
+        in_data: InData,
+        params: &mut ae::Parameters<Params>,
+        id: Params,
+        visible: bool,
+    ) -> Result<(), Error> {
+        if in_data.is_premiere() {
+            return Self::set_param_ui_flag(params, id, ae::pf::ParamUIFlags::INVISIBLE, !visible);
+        }
+
+        if let Some(plugin_id) = self.aegp_id {
+            let effect = in_data.effect();
+            if let Some(index) = params.index(id)
+                && let Ok(effect_ref) = effect.aegp_effect(plugin_id)
+                && let Ok(stream) = effect_ref.new_stream_by_index(plugin_id, index as i32)
+            {
+                return stream.set_dynamic_stream_flag(
+                    ae::aegp::DynamicStreamFlags::Hidden,
+                    false,
+                    !visible,
+                );
+            }
+        }
+
+        Self::set_param_ui_flag(params, id, ae::pf::ParamUIFlags::INVISIBLE, !visible)
+    }
+
+    fn set_param_enabled(
+        params: &mut ae::Parameters<Params>,
+        id: Params,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        Self::set_param_ui_flag(params, id, ae::pf::ParamUIFlags::DISABLED, !enabled)
+    }
+
+    fn set_param_ui_flag(
+        params: &mut ae::Parameters<Params>,
+        id: Params,
+        flag: ae::pf::ParamUIFlags,
+        status: bool,
+    ) -> Result<(), Error> {
+        let flag_bits = flag.bits();
+        let current_status = (params.get(id)?.ui_flags().bits() & flag_bits) != 0;
+        if current_status == status {
+            return Ok(());
+        }
+        let mut p = params.get_mut(id)?;
+        p.set_ui_flag(flag, status);
+        p.update_param_ui()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "gpu_wgpu")]
+    fn do_render_wgpu(
+        &self,
+        in_data: InData,
+        in_layer: &Layer,
+        out_layer: &mut Layer,
+        params: &mut Parameters<Params>,
+        ctx: &WgpuContext,
+    ) -> Result<(), Error> {
         let out_w = out_layer.width();
         let out_h = out_layer.height();
         if out_w == 0 || out_h == 0 {
             return Ok(());
         }
 
+        // The shader has no per-pixel W input; let the CPU path handle W maps.
+        let w_map_checkout = params.checkout_at(Params::WMapLayer, None, None, None)?;
+        if w_map_checkout.as_layer()?.value().is_some() {
+            return Err(Error::BadCallbackParameter);
+        }
+        // Site repulsion needs the neighbouring sites, which the shader never sees together.
+        if params
+            .get(Params::MinSiteDistance)?
+            .as_float_slider()?
+            .value()
+            > 0.0
+        {
+            return Err(Error::BadCallbackParameter);
+        }
+        // Gradient cell fills need the second site's position, which the shader drops.
+        if params.get(Params::OutputType)?.as_popup()?.value() == 1
+            && params.get(Params::CellFill)?.as_popup()?.value() != 1
+        {
+            return Err(Error::BadCallbackParameter);
+        }
+        // The shader has no Perlin noise; warped lookups render on the CPU.
+        if params.get(Params::WarpNoise)?.as_float_slider()?.value() > 0.0 {
+            return Err(Error::BadCallbackParameter);
+        }
+        // Auto levels needs the whole distance buffer before writing, and the shader
+        // only tracks F1/F2.
+        if params.get(Params::OutputType)?.as_popup()?.value() == 4
+            && (params.get(Params::AutoLevels)?.as_checkbox()?.value()
+                || params.get(Params::FeatureFormula)?.as_popup()?.value() != 1
+                || params.get(Params::OctaveCount)?.as_slider()?.value() > 1)
+        {
+            return Err(Error::BadCallbackParameter);
+        }
+
         let out_world_type = out_layer.world_type();
         let out_is_f32 = matches!(
             out_world_type,
@@ -440,11 +1341,15 @@ impl Plugin {
             2 => 1,
             3 => 2,
             4 => 3,
+            5 => 4,
             _ => 0,
         };
+        let cylinder_radius = params
+            .get(Params::CylinderRadius)?
+            .as_float_slider()?
+            .value() as f32;
 
-        let lp_exp = params.get(Params::LpExponent)?.as_float_slider()?.value() as f32;
-        let lp_exp = lp_exp.max(0.1);
+        let lp_exp = lp_exponents(params)?;
 
         let smoothness = params.get(Params::Smoothness)?.as_float_slider()?.value() as f32;
         let smoothness = smoothness.clamp(0.0, 1.0);
@@ -454,13 +1359,45 @@ impl Plugin {
             3 => 2,
             4 => 3,
             5 => 4,
+            6 => 5,
+            7 => 6,
+            8 => 7,
+            // Mesh lines are rasterized from the site buffer on the CPU.
+            9 => return Err(Error::BadCallbackParameter),
+            10 => 8,
+            11 => 9,
             _ => 0,
         };
+        let boundary_blend = output_type == 5;
+        let reveal_output = output_type == 9;
+        let stipple_output = output_type == 7;
+        let position_output = output_type == 1;
+        let edge_thickness = params
+            .get(Params::EdgeThickness)?
+            .as_float_slider()?
+            .value() as f32;
+        let edge_softness = params.get(Params::EdgeSoftness)?.as_float_slider()?.value() as f32;
+        let position_encoding = match params.get(Params::PositionEncoding)?.as_popup()?.value() {
+            2 => PositionEncoding::Centered,
+            3 => PositionEncoding::Raw,
+            _ => PositionEncoding::Normalized,
+        };
+        let stipple = stipple_settings(params)?;
+        let cell_uv = cell_uv_settings(params, inv_cell_x, inv_cell_y)?;
+        let reveal = reveal_settings(params)?;
 
         let w_value = params.get(Params::W)?.as_float_slider()?.value() as f32;
         let offset_param = params.get(Params::Offset)?;
         let offset_point = offset_param.as_point()?;
         let (offset_x, offset_y) = point_value_f32(&offset_point);
+        let wave_amount = params.get(Params::WaveAmount)?.as_float_slider()?.value() as f32;
+        let wave_frequency = params
+            .get(Params::WaveFrequency)?
+            .as_float_slider()?
+            .value() as f32;
+        let wave_angle = params.get(Params::WaveAngle)?.as_float_slider()?.value() as f32;
+        let wave_angle = wave_angle.to_radians();
+        let motion = site_motion(in_data, params)?;
         let clamp_32 = params.get(Params::Clamp32)?.as_checkbox()?.value();
         let use_original_alpha = params.get(Params::UseOriginalAlpha)?.as_checkbox()?.value();
 
@@ -474,11 +1411,28 @@ impl Plugin {
             seed,
             distance_metric,
             lp_exp,
+            cylinder_radius,
             smoothness,
             output_type,
             w_value,
             offset_x,
             offset_y,
+            wave_amount,
+            wave_frequency,
+            wave_angle,
+            flow_time: motion.flow_time,
+            flow_turbulence: motion.flow_turbulence,
+            jitter_time: motion.time,
+            jitter: motion.jitter,
+            jitter_frequency: motion.jitter_frequency,
+            edge_thickness,
+            edge_softness,
+            stipple_radius: stipple.radius,
+            stipple_softness: stipple.softness,
+            cell_uv_scale: cell_uv.scale,
+            cell_uv_offset: cell_uv.offset,
+            reveal_progress: reveal.progress,
+            reveal_fade: reveal.fade,
         };
 
         let output = ctx.render(&render_params)?;
@@ -488,30 +1442,64 @@ impl Plugin {
 
         out_layer.iterate(0, out_h as i32, None, |x, y, mut dst| {
             let idx = (y as usize * out_w + x as usize) * 4;
-            let mut r = sanitize_value(output.data[idx], out_is_f32, clamp_32);
-            let mut g = sanitize_value(output.data[idx + 1], out_is_f32, clamp_32);
-            let mut b = sanitize_value(output.data[idx + 2], out_is_f32, clamp_32);
-
-            let a = if use_original_alpha {
-                let mut out_alpha =
-                    read_pixel_f32(in_layer, in_world_type, x as usize, y as usize).alpha;
-                if !out_alpha.is_finite() {
-                    out_alpha = 0.0;
-                }
-                out_alpha = out_alpha.clamp(0.0, 1.0);
-                r *= out_alpha;
-                g *= out_alpha;
-                b *= out_alpha;
-                out_alpha
+            let out_px = if boundary_blend {
+                // The shader writes the raw F2 - F1 edge value for this mode.
+                let src_px = read_pixel_f32(in_layer, in_world_type, x as usize, y as usize);
+                boundary_pixel(src_px, output.data[idx], edge_thickness)
+            } else if reveal_output {
+                // The shader writes the cell's reveal amount in R.
+                let src_px = read_pixel_f32(in_layer, in_world_type, x as usize, y as usize);
+                reveal_pixel(src_px, output.data[idx], use_original_alpha)
+            } else if stipple_output {
+                // The shader writes the site color in RGB and dot coverage in alpha.
+                let src_px = read_pixel_f32(in_layer, in_world_type, x as usize, y as usize);
+                let site_color = PixelF32 {
+                    alpha: 1.0,
+                    red: output.data[idx],
+                    green: output.data[idx + 1],
+                    blue: output.data[idx + 2],
+                };
+                stipple_pixel(src_px, stipple.dot_color(site_color), output.data[idx + 3])
             } else {
-                1.0
-            };
-
-            let out_px = PixelF32 {
-                alpha: a,
-                red: r,
-                green: g,
-                blue: b,
+                let (mut r, mut g) = if position_output {
+                    // The shader writes normalized positions; re-encode them here.
+                    encode_position(
+                        output.data[idx],
+                        output.data[idx + 1],
+                        position_encoding,
+                        (out_w as f32, out_h as f32, offset_x, offset_y),
+                        out_is_f32,
+                        clamp_32,
+                    )
+                } else {
+                    (
+                        sanitize_value(output.data[idx], out_is_f32, clamp_32),
+                        sanitize_value(output.data[idx + 1], out_is_f32, clamp_32),
+                    )
+                };
+                let mut b = sanitize_value(output.data[idx + 2], out_is_f32, clamp_32);
+
+                let a = if use_original_alpha {
+                    let mut out_alpha =
+                        read_pixel_f32(in_layer, in_world_type, x as usize, y as usize).alpha;
+                    if !out_alpha.is_finite() {
+                        out_alpha = 0.0;
+                    }
+                    out_alpha = out_alpha.clamp(0.0, 1.0);
+                    r *= out_alpha;
+                    g *= out_alpha;
+                    b *= out_alpha;
+                    out_alpha
+                } else {
+                    1.0
+                };
+
+                PixelF32 {
+                    alpha: a,
+                    red: r,
+                    green: g,
+                    blue: b,
+                }
             };
 
             match out_world_type {
@@ -530,11 +1518,12 @@ impl Plugin {
 
     fn do_render(
         &self,
-        _in_data: InData,
+        in_data: InData,
         in_layer: Layer,
-        _out_data: OutData,
         mut out_layer: Layer,
         params: &mut Parameters<Params>,
+        origin: BufferOrigin,
+        levels_history: &Mutex<LevelsHistory>,
     ) -> Result<(), Error> {
         let w = out_layer.width();
         let h = out_layer.height();
@@ -548,33 +1537,6 @@ impl Plugin {
         let in_world_type = in_layer.world_type();
 
         // --- read params ---
-        let cell_size = params.get(Params::CellSize)?.as_float_slider()?.value() as f32;
-        let cell_size = cell_size.max(1.0e-3);
-        let scale_x = params.get(Params::ScaleX)?.as_float_slider()?.value() as f32;
-        let scale_y = params.get(Params::ScaleY)?.as_float_slider()?.value() as f32;
-        let scale_w = params.get(Params::ScaleW)?.as_float_slider()?.value() as f32;
-        let scale_x = scale_x.max(1.0e-3);
-        let scale_y = scale_y.max(1.0e-3);
-        let scale_w = scale_w.max(1.0e-3);
-        let inv_cell_x = scale_x / cell_size;
-        let inv_cell_y = scale_y / cell_size;
-        let inv_cell_w = scale_w / cell_size;
-
-        let randomness = params.get(Params::Randomness)?.as_float_slider()?.value() as f32;
-        let randomness = randomness.clamp(0.0, 1.0);
-
-        let seed = params.get(Params::Seed)?.as_slider()?.value() as u32;
-
-        let distance_metric = match params.get(Params::DistanceMetric)?.as_popup()?.value() {
-            2 => DistanceMetric::Manhattan,
-            3 => DistanceMetric::Chebyshev,
-            4 => DistanceMetric::Lp,
-            _ => DistanceMetric::Euclidean,
-        };
-
-        let lp_exp = params.get(Params::LpExponent)?.as_float_slider()?.value() as f32;
-        let lp_exp = lp_exp.max(0.1);
-
         let smoothness = params.get(Params::Smoothness)?.as_float_slider()?.value() as f32;
         let smoothness = smoothness.clamp(0.0, 1.0);
 
@@ -583,13 +1545,56 @@ impl Plugin {
             3 => OutputType::F,
             4 => OutputType::Distance,
             5 => OutputType::Edge,
+            6 => OutputType::BoundaryBlend,
+            7 => OutputType::Delaunay,
+            8 => OutputType::Stipple,
+            9 => OutputType::Mesh,
+            10 => OutputType::CellUV,
+            11 => OutputType::Reveal,
             _ => OutputType::Color,
         };
-
-        let w_value = params.get(Params::W)?.as_float_slider()?.value() as f32;
-        let offset_param = params.get(Params::Offset)?;
-        let offset_point = offset_param.as_point()?;
-        let (offset_x, offset_y) = point_value_f32(&offset_point);
+        let edge_thickness = params
+            .get(Params::EdgeThickness)?
+            .as_float_slider()?
+            .value() as f32;
+        let edge_softness = params.get(Params::EdgeSoftness)?.as_float_slider()?.value() as f32;
+        let position_encoding = match params.get(Params::PositionEncoding)?.as_popup()?.value() {
+            2 => PositionEncoding::Centered,
+            3 => PositionEncoding::Raw,
+            _ => PositionEncoding::Normalized,
+        };
+        let stipple = stipple_settings(params)?;
+        let reveal = reveal_settings(params)?;
+        let cell_fill = match params.get(Params::CellFill)?.as_popup()?.value() {
+            2 => CellFill::Radial,
+            3 => CellFill::Linear,
+            _ => CellFill::Flat,
+        };
+        let feature_formula = match output_type {
+            OutputType::Distance => {
+                FeatureFormula::from_popup(params.get(Params::FeatureFormula)?.as_popup()?.value())
+            }
+            _ => FeatureFormula::F1,
+        };
+        let auto_levels = params.get(Params::AutoLevels)?.as_checkbox()?.value();
+        let temporal_smoothing = params
+            .get(Params::TemporalSmoothing)?
+            .as_float_slider()?
+            .value() as f32;
+
+        let lattice = lattice_from_params(in_data, params, origin, feature_formula.uses_f3())?;
+        let Lattice {
+            inv_cell_x,
+            inv_cell_y,
+            inv_cell_w,
+            seed,
+            metric: distance_metric,
+            lp_exp,
+            offset_x,
+            offset_y,
+            ..
+        } = lattice;
+        let cell_uv = cell_uv_settings(params, inv_cell_x, inv_cell_y)?;
 
         let clamp_32 = params.get(Params::Clamp32)?.as_checkbox()?.value();
         let use_original_alpha = params.get(Params::UseOriginalAlpha)?.as_checkbox()?.value();
@@ -599,55 +1604,106 @@ impl Plugin {
         let grid_w = grid_w.max(1.0e-6);
         let grid_h = grid_h.max(1.0e-6);
 
-        out_layer.iterate(0, progress_final, None, |x, y, mut dst| {
-            let px = (x as f32 + 0.5 - offset_x) * inv_cell_x;
-            let py = (y as f32 + 0.5 - offset_y) * inv_cell_y;
-            let pw = w_value * inv_cell_w;
-            let cell_x = px.floor() as i32;
-            let cell_y = py.floor() as i32;
-            let cell_w = pw.floor() as i32;
-
-            let mut d1 = f32::INFINITY;
-            let mut d2 = f32::INFINITY;
-            let mut nearest = Site::default();
-            let mut second = Site::default();
-
-            for nw in (cell_w - 1)..=(cell_w + 1) {
-                for ny in (cell_y - 1)..=(cell_y + 1) {
-                    for nx in (cell_x - 1)..=(cell_x + 1) {
-                        let site = cell_point(nx, ny, nw, randomness, seed);
-                        let dx = px - site.x;
-                        let dy = py - site.y;
-                        let dw = pw - site.w;
-                        let d = metric_distance(dx, dy, dw, distance_metric, lp_exp);
-
-                        if d < d1 {
-                            d2 = d1;
-                            second = nearest;
-                            d1 = d;
-                            nearest = site;
-                        } else if d < d2 {
-                            d2 = d;
-                            second = site;
-                        }
-                    }
-                }
+        // The site search only depends on the lattice, so output-stage tweaks reuse it.
+        let w_map_checkout = params.checkout_at(Params::WMapLayer, None, None, None)?;
+        let w_map = w_map_checkout.as_layer()?.value();
+        let w_map_amount = params.get(Params::WMapAmount)?.as_float_slider()?.value() as f32;
+        let w_offsets = w_map
+            .as_ref()
+            .filter(|layer| layer.width() > 0 && layer.height() > 0)
+            .map(|layer| w_map_offsets(layer, w, h, w_map_amount * inv_cell_w));
+        // A W map changes the search per pixel, and the cache does not keep the
+        // second site or F3, so those cases run the full search.
+        let needs_full_search = w_offsets.is_some()
+            || feature_formula.uses_f3()
+            || (matches!(output_type, OutputType::Color) && smoothness > 0.0);
+        let samples = if needs_full_search {
+            build_site_samples(&lattice, w, h, w_offsets.as_deref())
+        } else {
+            cached_site_samples(&lattice, w, h)
+        };
+        let w_offset_at = |x: usize, y: usize| w_offsets.as_ref().map_or(0.0, |o| o[y * w + x]);
+        let delaunay_sites = match output_type {
+            OutputType::Delaunay => lattice.site_table(w, h, w_offsets.as_deref()),
+            _ => None,
+        };
+        let mesh = match output_type {
+            OutputType::Mesh => {
+                let color = params.get(Params::EdgeColor)?.as_color()?.float_value()?;
+                let alpha = params.get(Params::EdgeAlpha)?.as_float_slider()?.value() as f32;
+                let width = params.get(Params::EdgeWidth)?.as_float_slider()?.value() as f32;
+                let color = [color.red, color.green, color.blue, alpha.clamp(0.0, 1.0)];
+                Some(mesh_triangulation(&samples, &lattice, w, h, width, color))
             }
-
-            if !d1.is_finite() {
-                d1 = 0.0;
+            _ => None,
+        };
+        let octave_count = params.get(Params::OctaveCount)?.as_slider()?.value();
+        let multiscale_values = match output_type {
+            OutputType::Distance if octave_count > 1 => {
+                let lacunarity = params
+                    .get(Params::OctaveLacunarity)?
+                    .as_float_slider()?
+                    .value() as f32;
+                let gain = params.get(Params::OctaveGain)?.as_float_slider()?.value() as f32;
+                let multiscale = Multiscale::new(
+                    &lattice,
+                    octave_count.clamp(1, 8) as usize,
+                    lacunarity.clamp(1.0, 4.0),
+                    gain.clamp(0.0, 1.0),
+                    feature_formula,
+                );
+                Some(build_multiscale_values(
+                    &multiscale,
+                    &lattice,
+                    w,
+                    h,
+                    w_offsets.as_deref(),
+                ))
             }
-            if !d2.is_finite() {
-                d2 = d1;
-                second = nearest;
+            _ => None,
+        };
+        let distance_at = |i: usize| match &multiscale_values {
+            Some(values) => values[i],
+            None => feature_formula.eval(&samples[i]),
+        };
+        let levels = (auto_levels && matches!(output_type, OutputType::Distance)).then(|| {
+            let frame = LevelsFrame {
+                width: w,
+                height: h,
+                seed,
+                time: in_data.current_time(),
+                time_step: in_data.time_step(),
+                time_scale: in_data.time_scale(),
+            };
+            let levels = distance_levels(w * h, distance_at);
+            if temporal_smoothing > 0.0 {
+                lock_levels(levels_history).smoothed(frame, levels, temporal_smoothing)
+            } else {
+                levels
             }
+        });
+
+        out_layer.iterate(0, progress_final, None, |x, y, mut dst| {
+            let sample = samples[y as usize * w + x as usize];
+            let SiteSample {
+                d1,
+                d2,
+                nearest,
+                second,
+                dx,
+                dy,
+                ..
+            } = sample;
 
             let blend = smooth_blend(d1, d2, smoothness);
 
             let mut out_px = match output_type {
                 OutputType::Color => {
-                    let (r1, g1, b1) = hash_color(nearest.hash);
-                    let (r2, g2, b2) = hash_color(second.hash);
+                    // Offsets from the second site follow from the lookup point.
+                    let (px, py) = (nearest.x + dx, nearest.y + dy);
+                    let (r1, g1, b1) = cell_fill_color(&nearest, dx, dy, d1, d2, cell_fill);
+                    let (r2, g2, b2) =
+                        cell_fill_color(&second, px - second.x, py - second.y, d2, d1, cell_fill);
                     let r = lerp(r1, r2, blend);
                     let g = lerp(g1, g2, blend);
                     let b = lerp(b1, b2, blend);
@@ -659,13 +1715,15 @@ impl Plugin {
                     }
                 }
                 OutputType::Position => {
-                    let mut r = nearest.x / grid_w;
-                    let mut g = nearest.y / grid_h;
-                    let mut b = 0.0;
-
-                    r = sanitize_value(r, out_is_f32, clamp_32);
-                    g = sanitize_value(g, out_is_f32, clamp_32);
-                    b = sanitize_value(b, out_is_f32, clamp_32);
+                    let (r, g) = encode_position(
+                        nearest.x / grid_w,
+                        nearest.y / grid_h,
+                        position_encoding,
+                        (w as f32, h as f32, offset_x, offset_y),
+                        out_is_f32,
+                        clamp_32,
+                    );
+                    let b = sanitize_value(0.0, out_is_f32, clamp_32);
 
                     PixelF32 {
                         alpha: 1.0,
@@ -685,7 +1743,9 @@ impl Plugin {
                     }
                 }
                 OutputType::Distance => {
-                    let v = sanitize_value(d1, out_is_f32, clamp_32);
+                    let d = distance_at(y as usize * w + x as usize);
+                    let v = levels.map_or(d, |(lo, hi)| remap_levels(d, lo, hi));
+                    let v = sanitize_value(v, out_is_f32, clamp_32);
                     PixelF32 {
                         alpha: 1.0,
                         red: v,
@@ -703,34 +1763,792 @@ impl Plugin {
                         blue: v,
                     }
                 }
-            };
+                OutputType::BoundaryBlend => {
+                    let src_px = read_pixel_f32(&in_layer, in_world_type, x as usize, y as usize);
+                    boundary_pixel(src_px, d2 - d1, edge_thickness)
+                }
+                OutputType::Delaunay => {
+                    let (px, py, pw) = lattice.sample_point(x as usize, y as usize);
+                    let pw = pw + w_offset_at(x as usize, y as usize);
+                    let sites = find_three_nearest(
+                        px,
+                        py,
+                        pw,
+                        |nx, ny, nw| lattice.site_in(delaunay_sites.as_ref(), nx, ny, nw),
+                        distance_metric,
+                        lp_exp,
+                    );
+                    let v = delaunay_edge_value(px, py, &sites, edge_thickness, edge_softness);
+                    PixelF32 {
+                        alpha: 1.0,
+                        red: v,
+                        green: v,
+                        blue: v,
+                    }
+                }
+                OutputType::Mesh => {
+                    let i = (y as usize * w + x as usize) * 4;
+                    let px = mesh.as_ref().map_or([0.0, 0.0, 0.0, 1.0], |m| {
+                        [m[i], m[i + 1], m[i + 2], m[i + 3]]
+                    });
+                    PixelF32 {
+                        alpha: px[3],
+                        red: px[0],
+                        green: px[1],
+                        blue: px[2],
+                    }
+                }
+                OutputType::CellUV => {
+                    let (u, v) = cell_uv.uv(dx, dy, d2);
+                    PixelF32 {
+                        alpha: 1.0,
+                        red: u,
+                        green: v,
+                        blue: 0.0,
+                    }
+                }
+                OutputType::Reveal => {
+                    let src_px = read_pixel_f32(&in_layer, in_world_type, x as usize, y as usize);
+                    reveal_pixel(src_px, reveal.amount(nearest.hash), use_original_alpha)
+                }
+                OutputType::Stipple => {
+                    let src_px = read_pixel_f32(&in_layer, in_world_type, x as usize, y as usize);
+                    let (r, g, b) = hash_color(nearest.hash);
+                    let site_color = PixelF32 {
+                        alpha: 1.0,
+                        red: r,
+                        green: g,
+                        blue: b,
+                    };
+                    let coverage = stipple_coverage(dx, dy, stipple.radius, stipple.softness);
+                    stipple_pixel(src_px, stipple.dot_color(site_color), coverage)
+                }
+            };
+
+            // Segment boundaries, stippling and reveal already carry the input alpha.
+            if use_original_alpha
+                && !matches!(
+                    output_type,
+                    OutputType::BoundaryBlend | OutputType::Stipple | OutputType::Reveal
+                )
+            {
+                let mut out_alpha =
+                    read_pixel_f32(&in_layer, in_world_type, x as usize, y as usize).alpha;
+                if !out_alpha.is_finite() {
+                    out_alpha = 0.0;
+                }
+                out_alpha = out_alpha.clamp(0.0, 1.0);
+                out_px.red *= out_alpha;
+                out_px.green *= out_alpha;
+                out_px.blue *= out_alpha;
+                out_px.alpha = out_alpha;
+            }
+
+            match out_world_type {
+                ae::aegp::WorldType::U8 => dst.set_from_u8(out_px.to_pixel8()),
+                ae::aegp::WorldType::U15 => dst.set_from_u16(out_px.to_pixel16()),
+                ae::aegp::WorldType::F32 | ae::aegp::WorldType::None => {
+                    dst.set_from_f32(out_px);
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
+
+// Layer-space position of the render buffer's top-left pixel.
+#[derive(Clone, Copy, Default)]
+struct BufferOrigin(i32, i32);
+
+// Everything that decides where sites land and which one is nearest.
+#[derive(Clone, Copy)]
+struct Lattice {
+    inv_cell_x: f32,
+    inv_cell_y: f32,
+    inv_cell_w: f32,
+    randomness: f32,
+    seed: u32,
+    metric: DistanceMetric,
+    lp_exp: [f32; 3],
+    offset_x: f32,
+    offset_y: f32,
+    w_value: f32,
+    wave_amount: f32,
+    wave_frequency: f32,
+    wave_angle: f32,
+    motion: SiteMotion,
+    min_site_distance: f32,
+    repulsion_iters: usize,
+    origin_x: f32,
+    origin_y: f32,
+    // Search 5x5x5 cells instead of 3x3x3 so F3 stays correct at high randomness.
+    wide_search: bool,
+    warp_amount: f32,
+    warp_scale: f32,
+}
+
+impl Lattice {
+    fn sample_point(&self, x: usize, y: usize) -> (f32, f32, f32) {
+        let mut base_x = (x as f32 + self.origin_x + 0.5 - self.offset_x) * self.inv_cell_x;
+        let mut base_y = (y as f32 + self.origin_y + 0.5 - self.offset_y) * self.inv_cell_y;
+        if self.warp_amount > 0.0 {
+            let (nx, ny) = (base_x * self.warp_scale, base_y * self.warp_scale);
+            let warp_x = (perlin2d(nx, ny, self.seed) - 0.5) * self.warp_amount;
+            let warp_y = (perlin2d(nx, ny, self.seed ^ 0x5BD1_E995) - 0.5) * self.warp_amount;
+            base_x += warp_x;
+            base_y += warp_y;
+        }
+        let (wave_x, wave_y) = wave_offset(
+            base_x,
+            base_y,
+            self.wave_amount,
+            self.wave_frequency,
+            self.wave_angle,
+        );
+        (
+            base_x + wave_x,
+            base_y + wave_y,
+            self.w_value * self.inv_cell_w,
+        )
+    }
+
+    fn site(&self, nx: i32, ny: i32, nw: i32) -> Site {
+        if self.min_site_distance > 0.0 {
+            return self.relaxed_site(nx, ny, nw);
+        }
+        cell_point(nx, ny, nw, self.randomness, self.seed, &self.motion)
+    }
+
+    fn site_in(&self, table: Option<&SiteTable>, nx: i32, ny: i32, nw: i32) -> Site {
+        table
+            .and_then(|t| t.get(nx, ny, nw))
+            .unwrap_or_else(|| self.site(nx, ny, nw))
+    }
+
+    // Slow path for cells outside any site table: relaxes the block of cells
+    // that can influence this one and keeps only its site.
+    fn relaxed_site(&self, nx: i32, ny: i32, nw: i32) -> Site {
+        let reach = self.repulsion_iters as i32;
+        let side = 2 * self.repulsion_iters + 1;
+        let sites = self.relax_block([nx - reach, ny - reach, nw - reach], [side; 3], 1);
+        sites[sites.len() / 2]
+    }
+
+    // Relaxes the sites of a box of cells, x fastest. Each step pushes every site
+    // away from its 26 neighbours' positions after the previous step, so a pair
+    // always moves apart symmetrically, and the box loses one exact ring of cells
+    // per step. Sites stay `min_site_distance / 2` inside their own cell, which
+    // keeps the minimum spacing whatever the repulsion reached.
+    fn relax_block(&self, origin: [i32; 3], size: [usize; 3], threads: usize) -> Vec<Site> {
+        let inset = self.min_site_distance * 0.5;
+        let cell_of = |i: usize| {
+            [
+                origin[0] + (i % size[0]) as i32,
+                origin[1] + (i / size[0] % size[1]) as i32,
+                origin[2] + (i / (size[0] * size[1])) as i32,
+            ]
+        };
+        let mut sites = vec![Site::default(); size[0] * size[1] * size[2]];
+        for_each_chunk(&mut sites, threads, |i0, chunk| {
+            for (i, site) in chunk.iter_mut().enumerate() {
+                let [nx, ny, nw] = cell_of(i0 + i);
+                let raw = cell_point(nx, ny, nw, self.randomness, self.seed, &self.motion);
+                *site = clamp_to_cell(raw, [nx, ny, nw], inset);
+            }
+        });
+
+        let min_dist = self.min_site_distance;
+        for _ in 0..self.repulsion_iters {
+            let previous = sites.clone();
+            let previous = &previous;
+            for_each_chunk(&mut sites, threads, |i0, chunk| {
+                for (i, site) in chunk.iter_mut().enumerate() {
+                    let n = cell_of(i0 + i);
+                    let here = previous[i0 + i];
+                    let mut moved = here;
+                    for dw in -1..=1 {
+                        for dy in -1..=1 {
+                            for dx in -1..=1 {
+                                let m = [n[0] + dx, n[1] + dy, n[2] + dw];
+                                let Some(j) = block_index(origin, size, m) else {
+                                    continue;
+                                };
+                                if j == i0 + i {
+                                    continue;
+                                }
+                                let other = previous[j];
+                                let ox = other.x - here.x;
+                                let oy = other.y - here.y;
+                                let ow = other.w - here.w;
+                                let dist = (ox * ox + oy * oy + ow * ow).sqrt();
+                                if dist >= min_dist || dist <= 1.0e-6 {
+                                    continue;
+                                }
+                                let push = (min_dist - dist) * 0.5 / dist;
+                                moved.x -= ox * push;
+                                moved.y -= oy * push;
+                                moved.w -= ow * push;
+                            }
+                        }
+                    }
+                    *site = clamp_to_cell(moved, n, inset);
+                }
+            });
+        }
+        sites
+    }
+
+    // Relaxing is far too costly per lookup, so the relaxed sites covering the
+    // frame are built once. None when repulsion is off or the table is huge.
+    fn site_table(
+        &self,
+        width: usize,
+        height: usize,
+        w_offsets: Option<&[f32]>,
+    ) -> Option<SiteTable> {
+        if self.min_site_distance <= 0.0 || width == 0 || height == 0 {
+            return None;
+        }
+
+        let mut lo = [i32::MAX; 3];
+        let mut hi = [i32::MIN; 3];
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py, pw) = self.sample_point(x, y);
+                let pw = pw + w_offsets.map_or(0.0, |o| o[y * width + x]);
+                for (axis, v) in [px, py, pw].into_iter().enumerate() {
+                    if v.is_finite() {
+                        lo[axis] = lo[axis].min(v.floor() as i32);
+                        hi[axis] = hi[axis].max(v.floor() as i32);
+                    }
+                }
+            }
+        }
+        if lo.iter().zip(&hi).any(|(l, h)| l > h) {
+            return None;
+        }
+        // Delaunay and the wide search look two cells out, the F1/F2 search one.
+        let margin = [2, 2, if self.wide_search { 2 } else { 1 }];
+        let origin = [0, 1, 2].map(|a| lo[a].saturating_sub(margin[a]));
+        let size = [0, 1, 2].map(|a| {
+            (hi[a].saturating_add(margin[a]) as i64 - origin[a] as i64 + 1).max(0) as usize
+        });
+        let count = size[0].checked_mul(size[1])?.checked_mul(size[2])?;
+        let padded_count = size
+            .map(|s| s + 2 * self.repulsion_iters)
+            .iter()
+            .try_fold(1usize, |acc, &s| acc.checked_mul(s))?;
+        if count == 0 || padded_count > SITE_TABLE_MAX_CELLS {
+            return None;
+        }
+
+        // Relaxation leaves the outer `repulsion_iters` rings inexact, so relax a
+        // padded box and keep its interior.
+        let pad = self.repulsion_iters;
+        let padded_origin = origin.map(|o| o.saturating_sub(pad as i32));
+        let padded_size = size.map(|s| s + 2 * pad);
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let padded = self.relax_block(padded_origin, padded_size, threads);
+        let mut sites = Vec::with_capacity(count);
+        for iw in 0..size[2] {
+            for iy in 0..size[1] {
+                let row = ((iw + pad) * padded_size[1] + iy + pad) * padded_size[0] + pad;
+                sites.extend_from_slice(&padded[row..row + size[0]]);
+            }
+        }
+
+        Some(SiteTable {
+            origin,
+            size,
+            sites,
+        })
+    }
+
+    fn search(&self, x: usize, y: usize, w_offset: f32, table: Option<&SiteTable>) -> SiteSample {
+        let (px, py, pw) = self.sample_point(x, y);
+        self.search_point(px, py, pw + w_offset, table)
+    }
+
+    fn search_point(&self, px: f32, py: f32, pw: f32, table: Option<&SiteTable>) -> SiteSample {
+        let cell_x = px.floor() as i32;
+        let cell_y = py.floor() as i32;
+        let cell_w = pw.floor() as i32;
+
+        let mut d1 = f32::INFINITY;
+        let mut d2 = f32::INFINITY;
+        let mut d3 = f32::INFINITY;
+        let mut nearest = Site::default();
+        let mut second = Site::default();
+
+        let r = if self.wide_search { 2 } else { 1 };
+        for nw in (cell_w - r)..=(cell_w + r) {
+            for ny in (cell_y - r)..=(cell_y + r) {
+                for nx in (cell_x - r)..=(cell_x + r) {
+                    let site = self.site_in(table, nx, ny, nw);
+                    let dx = px - site.x;
+                    let dy = py - site.y;
+                    let dw = pw - site.w;
+                    let d = metric_distance(dx, dy, dw, self.metric, self.lp_exp);
+
+                    if d < d1 {
+                        d3 = d2;
+                        d2 = d1;
+                        second = nearest;
+                        d1 = d;
+                        nearest = site;
+                    } else if d < d2 {
+                        d3 = d2;
+                        d2 = d;
+                        second = site;
+                    } else if d < d3 {
+                        d3 = d;
+                    }
+                }
+            }
+        }
+
+        if !d1.is_finite() {
+            d1 = 0.0;
+        }
+        if !d2.is_finite() {
+            d2 = d1;
+            second = nearest;
+        }
+        if !d3.is_finite() {
+            d3 = d2;
+        }
+
+        SiteSample {
+            d1,
+            d2,
+            d3,
+            nearest,
+            second,
+            dx: px - nearest.x,
+            dy: py - nearest.y,
+        }
+    }
+
+    fn key(&self, width: usize, height: usize) -> LatticeKey {
+        let (metric, metric_arg) = match self.metric {
+            DistanceMetric::Euclidean => (0, 0.0),
+            DistanceMetric::Manhattan => (1, 0.0),
+            DistanceMetric::Chebyshev => (2, 0.0),
+            DistanceMetric::Lp => (3, 0.0),
+            DistanceMetric::Cylindrical(radius) => (4, radius),
+        };
+        let values = [
+            self.inv_cell_x,
+            self.inv_cell_y,
+            self.inv_cell_w,
+            self.randomness,
+            metric_arg,
+            self.lp_exp[0],
+            self.lp_exp[1],
+            self.lp_exp[2],
+            self.offset_x,
+            self.offset_y,
+            self.w_value,
+            self.wave_amount,
+            self.wave_frequency,
+            self.wave_angle,
+            self.motion.flow_time,
+            self.motion.flow_turbulence,
+            self.motion.time,
+            self.motion.jitter,
+            self.motion.jitter_frequency,
+            self.min_site_distance,
+            self.repulsion_iters as f32,
+            self.origin_x,
+            self.origin_y,
+            if self.wide_search { 1.0 } else { 0.0 },
+            self.warp_amount,
+            self.warp_scale,
+        ];
+        LatticeKey {
+            width,
+            height,
+            seed: self.seed,
+            metric,
+            bits: values.map(f32::to_bits),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+struct LatticeKey {
+    width: usize,
+    height: usize,
+    seed: u32,
+    metric: u32,
+    bits: [u32; 26],
+}
+
+// Larger tables would cost more memory than the per-lookup fallback saves.
+const SITE_TABLE_MAX_CELLS: usize = 1 << 22;
+
+// Relaxed sites for a box of lattice cells, x fastest.
+struct SiteTable {
+    origin: [i32; 3],
+    size: [usize; 3],
+    sites: Vec<Site>,
+}
+
+impl SiteTable {
+    fn get(&self, nx: i32, ny: i32, nw: i32) -> Option<Site> {
+        block_index(self.origin, self.size, [nx, ny, nw]).map(|i| self.sites[i])
+    }
+}
+
+// Per-pixel result of the nearest-site search.
+#[derive(Clone, Copy, Default)]
+struct SiteSample {
+    d1: f32,
+    d2: f32,
+    d3: f32,
+    nearest: Site,
+    second: Site,
+    dx: f32,
+    dy: f32,
+}
+
+// What the lattice cache keeps per pixel: the two nearest distances and the
+// nearest site. Outputs that need the second site or F3 run the full search.
+#[derive(Clone, Copy, Default)]
+struct CachedSample {
+    d1: f32,
+    d2: f32,
+    hash: u32,
+    x: f32,
+    y: f32,
+}
+
+impl CachedSample {
+    fn new(sample: &SiteSample) -> Self {
+        Self {
+            d1: sample.d1,
+            d2: sample.d2,
+            hash: sample.nearest.hash,
+            x: sample.nearest.x,
+            y: sample.nearest.y,
+        }
+    }
+
+    // Rebuilds the search result for pixel (x, y). The lookup offset follows
+    // from the lattice; the second site and F3 are not kept and read as the
+    // nearest site and F2.
+    fn expand(&self, lattice: &Lattice, x: usize, y: usize) -> SiteSample {
+        let (px, py, _) = lattice.sample_point(x, y);
+        let nearest = Site {
+            x: self.x,
+            y: self.y,
+            w: 0.0,
+            hash: self.hash,
+        };
+        SiteSample {
+            d1: self.d1,
+            d2: self.d2,
+            d3: self.d2,
+            nearest,
+            second: nearest,
+            dx: px - self.x,
+            dy: py - self.y,
+        }
+    }
+}
+
+// Total size of the cached buffers. Two 4K lattices fit; a buffer larger than
+// this is never cached.
+const SITE_CACHE_MAX_BYTES: usize = 384 << 20;
+const SITE_CACHE_ENTRIES: usize = 2;
+
+static SITE_CACHE: Mutex<Vec<(LatticeKey, Arc<Vec<CachedSample>>)>> = Mutex::new(Vec::new());
+
+fn cached_site_samples(lattice: &Lattice, width: usize, height: usize) -> Vec<SiteSample> {
+    let key = lattice.key(width, height);
+    let hit = match SITE_CACHE.lock() {
+        Ok(cache) => cache
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, samples)| samples.clone()),
+        Err(_) => None,
+    };
+    if let Some(cached) = hit {
+        return par_pixels(width, height, |x, y| {
+            cached[y * width + x].expand(lattice, x, y)
+        });
+    }
+
+    // Built outside the lock so concurrent MFR renders are not serialized; the
+    // finished buffer is shared read-only.
+    let samples = build_site_samples(lattice, width, height, None);
+    let bytes = width * height * std::mem::size_of::<CachedSample>();
+    if bytes <= SITE_CACHE_MAX_BYTES
+        && let Ok(mut cache) = SITE_CACHE.lock()
+        && !cache.iter().any(|(k, _)| *k == key)
+    {
+        let compact = Arc::new(samples.iter().map(CachedSample::new).collect::<Vec<_>>());
+        let cached_bytes = |cache: &[(LatticeKey, Arc<Vec<CachedSample>>)]| {
+            cache
+                .iter()
+                .map(|(_, c)| c.len() * std::mem::size_of::<CachedSample>())
+                .sum::<usize>()
+        };
+        while !cache.is_empty()
+            && (cache.len() >= SITE_CACHE_ENTRIES
+                || cached_bytes(&cache) + bytes > SITE_CACHE_MAX_BYTES)
+        {
+            cache.remove(0);
+        }
+        cache.push((key, compact));
+    }
+    samples
+}
+
+// Percentiles are taken from a strided subset; this many values is plenty.
+const LEVELS_SAMPLE_LIMIT: usize = 1 << 16;
+
+// 1st and 99th percentile of the `len` distance values over the frame.
+fn distance_levels(len: usize, value_at: impl Fn(usize) -> f32) -> (f32, f32) {
+    let stride = len.div_ceil(LEVELS_SAMPLE_LIMIT).max(1);
+    let mut values: Vec<f32> = (0..len)
+        .step_by(stride)
+        .map(value_at)
+        .filter(|v| v.is_finite())
+        .collect();
+    if values.is_empty() {
+        return (0.0, 1.0);
+    }
+    let last = values.len() - 1;
+    let lo_index = last / 100;
+    let hi_index = last - last / 100;
+    let (_, hi, _) = values.select_nth_unstable_by(hi_index, f32::total_cmp);
+    let hi = *hi;
+    let (_, lo, _) = values[..=hi_index].select_nth_unstable_by(lo_index, f32::total_cmp);
+    (*lo, hi)
+}
+
+fn remap_levels(v: f32, lo: f32, hi: f32) -> f32 {
+    let range = hi - lo;
+    if range > 1.0e-6 {
+        ((v - lo) / range).clamp(0.0, 1.0)
+    } else {
+        v
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct LevelsFrame {
+    width: usize,
+    height: usize,
+    seed: u32,
+    time: i32,
+    time_step: i32,
+    time_scale: u32,
+}
+
+impl LevelsFrame {
+    fn same_frame(&self, other: &LevelsFrame) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.seed == other.seed
+            && self.time == other.time
+            && self.time_scale == other.time_scale
+    }
+
+    // Whether `self` is the frame one step before `next`.
+    fn precedes(&self, next: &LevelsFrame) -> bool {
+        self.width == next.width
+            && self.height == next.height
+            && self.seed == next.seed
+            && self.time_scale == next.time_scale
+            && next.time_step != 0
+            && self.time.checked_add(next.time_step) == Some(next.time)
+    }
+}
+
+// Smoothed levels of recently rendered frames, keyed by frame, for Temporal
+// Smoothing. A frame averages with the entry one step before it, so a re-render
+// of the same frame gives the same result.
+#[derive(Default)]
+struct LevelsHistory {
+    frames: Vec<(LevelsFrame, (f32, f32))>,
+}
+
+const LEVELS_HISTORY_FRAMES: usize = 8;
+
+impl LevelsHistory {
+    fn smoothed(&mut self, frame: LevelsFrame, levels: (f32, f32), smoothing: f32) -> (f32, f32) {
+        let smoothing = smoothing.clamp(0.0, 0.99);
+        let smoothed = match self.frames.iter().find(|(prev, _)| prev.precedes(&frame)) {
+            Some(&(_, (lo, hi))) => (lerp(levels.0, lo, smoothing), lerp(levels.1, hi, smoothing)),
+            None => levels,
+        };
+        self.frames.retain(|(prev, _)| !prev.same_frame(&frame));
+        if self.frames.len() >= LEVELS_HISTORY_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push((frame, smoothed));
+        smoothed
+    }
+}
+
+fn lock_levels(history: &Mutex<LevelsHistory>) -> std::sync::MutexGuard<'_, LevelsHistory> {
+    match history.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// Octaves of the distance feature for fractal Voronoi. Octave k samples the
+// point scaled by lacunarity^k with its own seed and is weighted by gain^k.
+struct Multiscale {
+    lattices: Vec<Lattice>,
+    lacunarity: f32,
+    gain: f32,
+    formula: FeatureFormula,
+}
+
+impl Multiscale {
+    fn new(
+        base: &Lattice,
+        count: usize,
+        lacunarity: f32,
+        gain: f32,
+        formula: FeatureFormula,
+    ) -> Self {
+        // Octave 0 keeps the base seed so a single octave matches the plain output.
+        // Only octave 0 relaxes its sites: finer octaves cover too many cells
+        // to tabulate, and relaxing every lookup there is far too slow.
+        let lattices = (0..count as u32)
+            .map(|k| {
+                if k == 0 {
+                    *base
+                } else {
+                    Lattice {
+                        seed: hash_u32(base.seed ^ k.wrapping_mul(0x9E37_79B9)),
+                        min_site_distance: 0.0,
+                        ..*base
+                    }
+                }
+            })
+            .collect();
+        Self {
+            lattices,
+            lacunarity,
+            gain,
+            formula,
+        }
+    }
+}
 
-            if use_original_alpha {
-                let mut out_alpha =
-                    read_pixel_f32(&in_layer, in_world_type, x as usize, y as usize).alpha;
-                if !out_alpha.is_finite() {
-                    out_alpha = 0.0;
-                }
-                out_alpha = out_alpha.clamp(0.0, 1.0);
-                out_px.red *= out_alpha;
-                out_px.green *= out_alpha;
-                out_px.blue *= out_alpha;
-                out_px.alpha = out_alpha;
-            }
+// Amplitude-weighted sum of the feature over all octaves at lattice point
+// (px, py, pw), normalized by the sum of amplitudes. `base_table` holds the
+// relaxed sites of octave 0, the only octave that relaxes.
+fn multiscale_voronoi(
+    px: f32,
+    py: f32,
+    pw: f32,
+    params: &Multiscale,
+    base_table: Option<&SiteTable>,
+) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude_sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for (k, lattice) in params.lattices.iter().enumerate() {
+        let table = if k == 0 { base_table } else { None };
+        let sample = lattice.search_point(px * frequency, py * frequency, pw * frequency, table);
+        total += amplitude * params.formula.eval(&sample);
+        amplitude_sum += amplitude;
+        amplitude *= params.gain;
+        frequency *= params.lacunarity;
+    }
+    if amplitude_sum > 0.0 {
+        total / amplitude_sum
+    } else {
+        0.0
+    }
+}
 
-            match out_world_type {
-                ae::aegp::WorldType::U8 => dst.set_from_u8(out_px.to_pixel8()),
-                ae::aegp::WorldType::U15 => dst.set_from_u16(out_px.to_pixel16()),
-                ae::aegp::WorldType::F32 | ae::aegp::WorldType::None => {
-                    dst.set_from_f32(out_px);
-                }
-            }
+fn build_multiscale_values(
+    multiscale: &Multiscale,
+    lattice: &Lattice,
+    width: usize,
+    height: usize,
+    w_offsets: Option<&[f32]>,
+) -> Vec<f32> {
+    let base_table = multiscale
+        .lattices
+        .first()
+        .and_then(|base| base.site_table(width, height, w_offsets));
+    let base_table = base_table.as_ref();
+    par_pixels(width, height, |x, y| {
+        let (px, py, pw) = lattice.sample_point(x, y);
+        let pw = pw + w_offsets.map_or(0.0, |o| o[y * width + x]);
+        multiscale_voronoi(px, py, pw, multiscale, base_table)
+    })
+}
 
-            Ok(())
-        })?;
+fn build_site_samples(
+    lattice: &Lattice,
+    width: usize,
+    height: usize,
+    w_offsets: Option<&[f32]>,
+) -> Vec<SiteSample> {
+    let table = lattice.site_table(width, height, w_offsets);
+    let table = table.as_ref();
+    par_pixels(width, height, |x, y| {
+        let w_offset = w_offsets.map_or(0.0, |o| o[y * width + x]);
+        lattice.search(x, y, w_offset, table)
+    })
+}
 
-        Ok(())
+// Evaluates `f` for every pixel, row-major, split into row bands across threads.
+fn par_pixels<T: Copy + Default + Send>(
+    width: usize,
+    height: usize,
+    f: impl Fn(usize, usize) -> T + Sync,
+) -> Vec<T> {
+    let mut values = vec![T::default(); width * height];
+    if width == 0 || height == 0 {
+        return values;
+    }
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let rows_per_chunk = height.div_ceil(threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        for (chunk_index, chunk) in values.chunks_mut(rows_per_chunk * width).enumerate() {
+            scope.spawn(move || {
+                let y0 = chunk_index * rows_per_chunk;
+                for (i, value) in chunk.iter_mut().enumerate() {
+                    *value = f(i % width, y0 + i / width);
+                }
+            });
+        }
+    });
+    values
+}
+
+// Per-pixel W offsets in lattice units from the map's Rec.709 luminance. The map
+// is edge-clamped when it is smaller than the output.
+fn w_map_offsets(layer: &Layer, width: usize, height: usize, scale: f32) -> Vec<f32> {
+    let world_type = layer.world_type();
+    let max_x = layer.width().saturating_sub(1);
+    let max_y = layer.height().saturating_sub(1);
+    let mut offsets = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let px = read_pixel_f32(layer, world_type, x.min(max_x), y.min(max_y));
+            let luma = 0.2126 * px.red + 0.7152 * px.green + 0.0722 * px.blue;
+            let offset = luma * scale;
+            offsets.push(if offset.is_finite() { offset } else { 0.0 });
+        }
     }
+    offsets
 }
 
 fn point_value_f32(point: &PointDef<'_>) -> (f32, f32) {
@@ -741,25 +2559,481 @@ fn point_value_f32(point: &PointDef<'_>) -> (f32, f32) {
 }
 
 // --- voronoi helpers ---
-fn metric_distance(dx: f32, dy: f32, dw: f32, metric: DistanceMetric, lp_exp: f32) -> f32 {
+// Per-axis Lp exponents (x, y, w), clamped like the single exponent was.
+fn lp_exponents(params: &Parameters<Params>) -> Result<[f32; 3], Error> {
+    let x = params.get(Params::LpExponent)?.as_float_slider()?.value() as f32;
+    let x = x.max(0.1);
+    if params.get(Params::LpFollowX)?.as_checkbox()?.value() {
+        return Ok([x; 3]);
+    }
+    let y = params.get(Params::LpExponentY)?.as_float_slider()?.value() as f32;
+    let w = params.get(Params::LpExponentW)?.as_float_slider()?.value() as f32;
+    Ok([x, y.max(0.1), w.max(0.1)])
+}
+
+fn metric_distance(dx: f32, dy: f32, dw: f32, metric: DistanceMetric, lp_exp: [f32; 3]) -> f32 {
     match metric {
         DistanceMetric::Euclidean => (dx * dx + dy * dy + dw * dw).sqrt(),
         DistanceMetric::Manhattan => dx.abs() + dy.abs() + dw.abs(),
         DistanceMetric::Chebyshev => dx.abs().max(dy.abs()).max(dw.abs()),
         DistanceMetric::Lp => {
-            let p = lp_exp.max(0.1);
-            let s = dx.abs().powf(p) + dy.abs().powf(p) + dw.abs().powf(p);
+            let [px, py, pw] = lp_exp.map(|p| p.max(0.1));
+            // The outer root uses the mean exponent so equal exponents reduce to plain Lp.
+            let p = (px + py + pw) / 3.0;
+            let s = dx.abs().powf(px) + dy.abs().powf(py) + dw.abs().powf(pw);
             s.powf(1.0 / p)
         }
+        // Cells hollow out into tubes along W.
+        DistanceMetric::Cylindrical(radius) => {
+            (dx * dx + dy * dy).sqrt() - radius * (1.0 - dw.abs().min(1.0))
+        }
+    }
+}
+
+// Flow time in cycles (comp seconds * speed) and the effective turbulence.
+// Time-driven site movement. Disabled effects report zeros so a still lattice
+// keeps the same cache key on every frame.
+#[derive(Clone, Copy, Default)]
+struct SiteMotion {
+    flow_time: f32,
+    flow_turbulence: f32,
+    time: f32,
+    jitter: f32,
+    jitter_frequency: f32,
+}
+
+// Lattice for the current params. `origin` places the buffer in layer space and
+// `wide_search` is needed by features that read F3.
+fn lattice_from_params(
+    in_data: InData,
+    params: &Parameters<Params>,
+    origin: BufferOrigin,
+    wide_search: bool,
+) -> Result<Lattice, Error> {
+    let cell_size = params.get(Params::CellSize)?.as_float_slider()?.value() as f32;
+    let cell_size = cell_size.max(1.0e-3);
+    let scale_x = params.get(Params::ScaleX)?.as_float_slider()?.value() as f32;
+    let scale_y = params.get(Params::ScaleY)?.as_float_slider()?.value() as f32;
+    let scale_w = params.get(Params::ScaleW)?.as_float_slider()?.value() as f32;
+    let scale_x = scale_x.max(1.0e-3);
+    let scale_y = scale_y.max(1.0e-3);
+    let scale_w = scale_w.max(1.0e-3);
+    let inv_cell_x = scale_x / cell_size;
+    let inv_cell_y = scale_y / cell_size;
+    let inv_cell_w = scale_w / cell_size;
+
+    let randomness = params.get(Params::Randomness)?.as_float_slider()?.value() as f32;
+    let randomness = randomness.clamp(0.0, 1.0);
+
+    let seed = params.get(Params::Seed)?.as_slider()?.value() as u32;
+    let min_site_distance = params
+        .get(Params::MinSiteDistance)?
+        .as_float_slider()?
+        .value() as f32;
+    let min_site_distance = min_site_distance.clamp(0.0, 1.0);
+    let repulsion_iters = params.get(Params::RepulsionIters)?.as_slider()?.value();
+    let repulsion_iters = repulsion_iters.clamp(1, 10) as usize;
+
+    let distance_metric = match params.get(Params::DistanceMetric)?.as_popup()?.value() {
+        2 => DistanceMetric::Manhattan,
+        3 => DistanceMetric::Chebyshev,
+        4 => DistanceMetric::Lp,
+        5 => DistanceMetric::Cylindrical(
+            params
+                .get(Params::CylinderRadius)?
+                .as_float_slider()?
+                .value() as f32,
+        ),
+        _ => DistanceMetric::Euclidean,
+    };
+
+    let lp_exp = lp_exponents(params)?;
+
+    let w_value = params.get(Params::W)?.as_float_slider()?.value() as f32;
+    let offset_param = params.get(Params::Offset)?;
+    let offset_point = offset_param.as_point()?;
+    let (offset_x, offset_y) = point_value_f32(&offset_point);
+    let wave_amount = params.get(Params::WaveAmount)?.as_float_slider()?.value() as f32;
+    let wave_frequency = params
+        .get(Params::WaveFrequency)?
+        .as_float_slider()?
+        .value() as f32;
+    let wave_angle = params.get(Params::WaveAngle)?.as_float_slider()?.value() as f32;
+    let wave_angle = wave_angle.to_radians();
+    let warp_amount = params.get(Params::WarpNoise)?.as_float_slider()?.value() as f32;
+    let warp_scale = params
+        .get(Params::WarpNoiseScale)?
+        .as_float_slider()?
+        .value() as f32;
+    let motion = site_motion(in_data, params)?;
+
+    Ok(Lattice {
+        inv_cell_x,
+        inv_cell_y,
+        inv_cell_w,
+        randomness,
+        seed,
+        metric: distance_metric,
+        lp_exp,
+        offset_x,
+        offset_y,
+        w_value,
+        wave_amount,
+        wave_frequency,
+        wave_angle,
+        motion,
+        min_site_distance,
+        repulsion_iters,
+        origin_x: origin.0 as f32,
+        origin_y: origin.1 as f32,
+        wide_search,
+        warp_amount: warp_amount.clamp(0.0, 1.0),
+        warp_scale: warp_scale.max(0.0),
+    })
+}
+
+// Writes the cells visible in the layer at the current time to a JSON file and
+// returns the cell count and path, or None if the save dialog was cancelled.
+fn export_cells(
+    in_data: InData,
+    params: &Parameters<Params>,
+) -> Result<Option<(usize, std::path::PathBuf)>, String> {
+    let w = in_data.width().max(0) as usize;
+    let h = in_data.height().max(0) as usize;
+    if w == 0 || h == 0 {
+        return Err("the layer is empty".to_string());
+    }
+    let lattice = lattice_from_params(in_data, params, BufferOrigin::default(), false)
+        .map_err(|err| format!("could not read params ({err:?})"))?;
+    if !matches!(lattice.metric, DistanceMetric::Euclidean) {
+        return Err("only the Euclidean metric has straight cell edges".to_string());
+    }
+    let time = in_data.current_time() as f32 / in_data.time_scale().max(1) as f32;
+    let export = cells_export(&lattice, w, h, time);
+    let json = serde_json::to_string_pretty(&export).map_err(|err| err.to_string())?;
+
+    let Some(path) = export_path() else {
+        return Ok(None);
+    };
+    std::fs::write(&path, json).map_err(|err| err.to_string())?;
+    Ok(Some((export.cells.len(), path)))
+}
+
+// The native save dialog is opt-in because it blocks AE's main thread until
+// closed. Without it the file goes to the temp directory and the path is
+// reported in AE's message box.
+#[cfg(feature = "export_dialog")]
+fn export_path() -> Option<std::path::PathBuf> {
+    rfd::FileDialog::new()
+        .set_title("Export Voronoi Cells")
+        .set_file_name("voronoi_cells.json")
+        .add_filter("JSON", &["json"])
+        .save_file()
+}
+
+#[cfg(not(feature = "export_dialog"))]
+fn export_path() -> Option<std::path::PathBuf> {
+    Some(std::env::temp_dir().join("voronoi_cells.json"))
+}
+
+// Lattice coordinates (x, y, w) of a cell, used as its id in the export.
+type CellId = [i32; 3];
+
+#[derive(serde::Serialize)]
+struct CellsExport {
+    width: usize,
+    height: usize,
+    time: f32,
+    cells: Vec<ExportedCell>,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedCell {
+    id: CellId,
+    x: f32,
+    y: f32,
+    neighbors: Vec<CellId>,
+    polygon: Vec<[f32; 2]>,
+}
+
+// Polygon vertex in lattice space. `edge` is the neighbour whose bisector runs
+// from this vertex to the next one, or None for the starting box.
+#[derive(Clone, Copy)]
+struct CellVertex {
+    x: f64,
+    y: f64,
+    edge: Option<CellId>,
+}
+
+// Cell search radius. Jittered sites stay within a cell of their own, so every
+// Voronoi neighbour lies within two cells.
+const EXPORT_NEIGHBOR_RADIUS: i32 = 2;
+
+// Exact Euclidean Voronoi cells of the W slice, in layer pixels. Each cell is
+// its site's box clipped by the bisector with every nearby site. Positions and
+// edges are before wave/warp distortion, and the W map is not applied.
+fn cells_export(lattice: &Lattice, w: usize, h: usize, time: f32) -> CellsExport {
+    let to_lattice_x = |px: f64| (px - lattice.offset_x as f64) * lattice.inv_cell_x as f64;
+    let to_lattice_y = |py: f64| (py - lattice.offset_y as f64) * lattice.inv_cell_y as f64;
+    let to_pixel = |lx: f64, ly: f64| {
+        [
+            (lx / lattice.inv_cell_x as f64) as f32 + lattice.offset_x,
+            (ly / lattice.inv_cell_y as f64) as f32 + lattice.offset_y,
+        ]
+    };
+    let (x0, x1) = (to_lattice_x(0.0), to_lattice_x(w as f64));
+    let (y0, y1) = (to_lattice_y(0.0), to_lattice_y(h as f64));
+    let pw = (lattice.w_value * lattice.inv_cell_w) as f64;
+    // The frame as half-planes a*x + b*y <= c.
+    let frame = [
+        (-1.0, 0.0, -x0),
+        (1.0, 0.0, x1),
+        (0.0, -1.0, -y0),
+        (0.0, 1.0, y1),
+    ];
+
+    let table = lattice.site_table(w, h, None);
+    let table = table.as_ref();
+    let r = EXPORT_NEIGHBOR_RADIUS;
+    let mut cells = Vec::new();
+    for iw in (pw.floor() as i32 - r)..=(pw.floor() as i32 + r) {
+        for iy in (y0.floor() as i32 - r)..=(y1.floor() as i32 + r) {
+            for ix in (x0.floor() as i32 - r)..=(x1.floor() as i32 + r) {
+                let site = lattice.site_in(table, ix, iy, iw);
+                let (sx, sy, sw) = (site.x as f64, site.y as f64, site.w as f64);
+                let reach = (r + 1) as f64;
+                let mut polygon: Vec<CellVertex> =
+                    [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                        .iter()
+                        .map(|(ux, uy)| CellVertex {
+                            x: sx + ux * reach,
+                            y: sy + uy * reach,
+                            edge: None,
+                        })
+                        .collect();
+                for nw in (iw - r)..=(iw + r) {
+                    for ny in (iy - r)..=(iy + r) {
+                        for nx in (ix - r)..=(ix + r) {
+                            if (nx, ny, nw) == (ix, iy, iw) || polygon.is_empty() {
+                                continue;
+                            }
+                            let other = lattice.site_in(table, nx, ny, nw);
+                            let (dx, dy, dw) = (
+                                other.x as f64 - sx,
+                                other.y as f64 - sy,
+                                other.w as f64 - sw,
+                            );
+                            // Closer to this site than to `other` within the slice:
+                            // 2 (p - s) . d <= |d|^2 with p.w fixed at pw.
+                            let c = 0.5 * (dx * dx + dy * dy + dw * dw) - (pw - sw) * dw
+                                + sx * dx
+                                + sy * dy;
+                            polygon = clip_cell(&polygon, (dx, dy, c), Some([nx, ny, nw]));
+                        }
+                    }
+                }
+                // Cells whose region misses this W slice or the frame are skipped.
+                let visible = frame.iter().fold(polygon.clone(), |poly, &plane| {
+                    clip_cell(&poly, plane, None)
+                });
+                if cell_area(&visible) <= 1.0e-9 {
+                    continue;
+                }
+
+                // Bisectors through a corner leave zero-length edges behind.
+                let polygon: Vec<CellVertex> = (0..polygon.len())
+                    .filter(|&i| {
+                        let (v, next) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                        (next.x - v.x).hypot(next.y - v.y) > 1.0e-9
+                    })
+                    .map(|i| polygon[i])
+                    .collect();
+                let mut neighbors: Vec<CellId> = Vec::new();
+                for id in polygon.iter().filter_map(|v| v.edge) {
+                    if !neighbors.contains(&id) {
+                        neighbors.push(id);
+                    }
+                }
+                let [x, y] = to_pixel(sx, sy);
+                cells.push(ExportedCell {
+                    id: [ix, iy, iw],
+                    x,
+                    y,
+                    neighbors,
+                    polygon: polygon.iter().map(|v| to_pixel(v.x, v.y)).collect(),
+                });
+            }
+        }
+    }
+    CellsExport {
+        width: w,
+        height: h,
+        time,
+        cells,
+    }
+}
+
+fn cell_area(polygon: &[CellVertex]) -> f64 {
+    let twice: f64 = (0..polygon.len())
+        .map(|i| {
+            let (v, next) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            v.x * next.y - next.x * v.y
+        })
+        .sum();
+    0.5 * twice.abs()
+}
+
+// Sutherland-Hodgman step keeping the side a*x + b*y <= c of a convex polygon.
+// The new edge along the clip line is tagged with `edge`.
+fn clip_cell(
+    polygon: &[CellVertex],
+    (a, b, c): (f64, f64, f64),
+    edge: Option<CellId>,
+) -> Vec<CellVertex> {
+    let side = |v: &CellVertex| a * v.x + b * v.y - c;
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (i, cur) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let (sc, sn) = (side(cur), side(next));
+        let crossing = || {
+            let t = sc / (sc - sn);
+            (cur.x + (next.x - cur.x) * t, cur.y + (next.y - cur.y) * t)
+        };
+        if sc <= 0.0 {
+            out.push(*cur);
+            if sn > 0.0 {
+                let (x, y) = crossing();
+                out.push(CellVertex { x, y, edge });
+            }
+        } else if sn <= 0.0 {
+            let (x, y) = crossing();
+            out.push(CellVertex {
+                x,
+                y,
+                edge: cur.edge,
+            });
+        }
+    }
+    out
+}
+
+// Flow and Jitter over Time move the sites with comp time alone, so only then
+// must AE skip its frame cache.
+fn varies_per_frame(params: &Parameters<Params>) -> Result<bool, Error> {
+    let speed = params.get(Params::FlowSpeed)?.as_float_slider()?.value();
+    let turbulence = params
+        .get(Params::FlowTurbulence)?
+        .as_float_slider()?
+        .value();
+    let jitter = params.get(Params::TimeJitter)?.as_float_slider()?.value();
+    Ok((speed > 0.0 && turbulence > 0.0) || jitter > 0.0)
+}
+
+fn site_motion(in_data: InData, params: &Parameters<Params>) -> Result<SiteMotion, Error> {
+    let time_scale = in_data.time_scale().max(1) as f32;
+    let seconds = in_data.current_time() as f32 / time_scale;
+    let mut motion = SiteMotion::default();
+
+    let speed = params.get(Params::FlowSpeed)?.as_float_slider()?.value() as f32;
+    let turbulence = params
+        .get(Params::FlowTurbulence)?
+        .as_float_slider()?
+        .value() as f32;
+    if speed > 0.0 && turbulence > 0.0 {
+        motion.flow_time = seconds * speed;
+        motion.flow_turbulence = turbulence.clamp(0.0, 1.0);
+    }
+
+    let jitter = params.get(Params::TimeJitter)?.as_float_slider()?.value() as f32;
+    let frequency = params
+        .get(Params::JitterFrequency)?
+        .as_float_slider()?
+        .value() as f32;
+    if jitter > 0.0 {
+        motion.time = seconds;
+        motion.jitter = jitter.clamp(0.0, 1.0);
+        motion.jitter_frequency = frequency.max(0.0);
+    }
+    Ok(motion)
+}
+
+fn flow_offset(h: u32, flow_time: f32, turbulence: f32) -> (f32, f32) {
+    if turbulence <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let phase_x = rand01(hash_u32(h ^ 0x2C1B_3C6D)) * std::f32::consts::TAU;
+    let phase_y = rand01(hash_u32(h ^ 0x297A_2D39)) * std::f32::consts::TAU;
+    let rate = 0.5 + rand01(hash_u32(h ^ 0x5851_F42D));
+    let t = flow_time * rate * std::f32::consts::TAU;
+    let amp = 0.5 * turbulence;
+    ((t + phase_x).sin() * amp, (t + phase_y).cos() * amp)
+}
+
+// Sine displacement in cell units, rotated by `angle` (radians).
+fn jitter_offset(h: u32, motion: &SiteMotion) -> (f32, f32) {
+    if motion.jitter <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let t = motion.time * motion.jitter_frequency + hash_to_phase(h);
+    let amp = 0.5 * motion.jitter;
+    (t.sin() * amp, t.cos() * amp)
+}
+
+fn hash_to_phase(h: u32) -> f32 {
+    rand01(hash_u32(h ^ 0x7F4A_7C15)) * std::f32::consts::TAU
+}
+
+fn wave_offset(px: f32, py: f32, amount: f32, frequency: f32, angle: f32) -> (f32, f32) {
+    if amount <= 0.0 {
+        return (0.0, 0.0);
     }
+    let wx = (py * frequency * std::f32::consts::TAU).sin() * amount;
+    let wy = (px * frequency * std::f32::consts::TAU).cos() * amount;
+    let (s, c) = angle.sin_cos();
+    (wx * c - wy * s, wx * s + wy * c)
+}
+
+// Gradient noise in 0..1 on the integer lattice, gradients drawn from hash3 so the
+// field follows the Voronoi seed.
+fn perlin2d(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let corner = |cx: i32, cy: i32, dx: f32, dy: f32| {
+        let angle = rand01(hash3(cx, cy, 0, seed)) * std::f32::consts::TAU;
+        let (s, c) = angle.sin_cos();
+        c * dx + s * dy
+    };
+    let n00 = corner(ix, iy, fx, fy);
+    let n10 = corner(ix + 1, iy, fx - 1.0, fy);
+    let n01 = corner(ix, iy + 1, fx, fy - 1.0);
+    let n11 = corner(ix + 1, iy + 1, fx - 1.0, fy - 1.0);
+    let (u, v) = (perlin_fade(fx), perlin_fade(fy));
+    let n = lerp(lerp(n00, n10, u), lerp(n01, n11, u), v);
+    // Unit gradients keep n within +-sqrt(1/2).
+    (0.5 + n * std::f32::consts::FRAC_1_SQRT_2).clamp(0.0, 1.0)
 }
 
-fn cell_point(cell_x: i32, cell_y: i32, cell_w: i32, randomness: f32, seed: u32) -> Site {
+fn perlin_fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn cell_point(
+    cell_x: i32,
+    cell_y: i32,
+    cell_w: i32,
+    randomness: f32,
+    seed: u32,
+    motion: &SiteMotion,
+) -> Site {
     let h = hash3(cell_x, cell_y, cell_w, seed);
     let rx = rand01(hash_u32(h ^ 0xA511_E9B3));
     let ry = rand01(hash_u32(h ^ 0x63D8_3595));
-    let ox = 0.5 + (rx - 0.5) * randomness;
-    let oy = 0.5 + (ry - 0.5) * randomness;
+    let (fx, fy) = flow_offset(h, motion.flow_time, motion.flow_turbulence);
+    let (jx, jy) = jitter_offset(h, motion);
+    // Clamped to the lattice cell so the 3x3 neighbourhood search stays valid.
+    let ox = (0.5 + (rx - 0.5) * randomness + fx + jx).clamp(0.0, 1.0);
+    let oy = (0.5 + (ry - 0.5) * randomness + fy + jy).clamp(0.0, 1.0);
     let rw = rand01(hash_u32(h ^ 0x1F1D_8E33));
     let ow = 0.5 + (rw - 0.5) * randomness;
     Site {
@@ -770,6 +3044,163 @@ fn cell_point(cell_x: i32, cell_y: i32, cell_w: i32, randomness: f32, seed: u32)
     }
 }
 
+// Keeps a site at least `inset` inside lattice cell `n`, so sites of distinct
+// cells are never closer than twice the inset.
+fn clamp_to_cell(site: Site, n: [i32; 3], inset: f32) -> Site {
+    let clamp = |v: f32, n: i32| v.clamp(n as f32 + inset, n as f32 + 1.0 - inset);
+    Site {
+        x: clamp(site.x, n[0]),
+        y: clamp(site.y, n[1]),
+        w: clamp(site.w, n[2]),
+        hash: site.hash,
+    }
+}
+
+// Index of lattice cell `n` in a box of cells, x fastest.
+fn block_index(origin: [i32; 3], size: [usize; 3], n: [i32; 3]) -> Option<usize> {
+    let ix = usize::try_from(n[0].checked_sub(origin[0])?).ok()?;
+    let iy = usize::try_from(n[1].checked_sub(origin[1])?).ok()?;
+    let iw = usize::try_from(n[2].checked_sub(origin[2])?).ok()?;
+    if ix >= size[0] || iy >= size[1] || iw >= size[2] {
+        return None;
+    }
+    Some((iw * size[1] + iy) * size[0] + ix)
+}
+
+// Runs `f(first_index, chunk)` over `values` split into `threads` chunks.
+fn for_each_chunk<T: Send>(values: &mut [T], threads: usize, f: impl Fn(usize, &mut [T]) + Sync) {
+    if threads <= 1 {
+        f(0, values);
+        return;
+    }
+    let per_chunk = values.len().div_ceil(threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        for (chunk_index, chunk) in values.chunks_mut(per_chunk).enumerate() {
+            scope.spawn(move || f(chunk_index * per_chunk, chunk));
+        }
+    });
+}
+
+// Colour of a cell at offset (dx, dy) from its site. `d_self` / `d_other` are
+// the distances to this site and the competing one; their ratio reaches 1 on
+// the shared edge.
+fn cell_fill_color(
+    site: &Site,
+    dx: f32,
+    dy: f32,
+    d_self: f32,
+    d_other: f32,
+    fill: CellFill,
+) -> (f32, f32, f32) {
+    let t = match fill {
+        CellFill::Flat => return hash_color(site.hash),
+        CellFill::Radial => {
+            let sum = d_self + d_other;
+            if sum > 1.0e-6 {
+                2.0 * d_self / sum
+            } else {
+                0.0
+            }
+        }
+        CellFill::Linear => {
+            let angle = rand01(hash_u32(site.hash ^ 0x3C6E_F372)) * std::f32::consts::TAU;
+            let (s, c) = angle.sin_cos();
+            0.5 + dx * c + dy * s
+        }
+    };
+    let t = t.clamp(0.0, 1.0);
+    let inner = hash_color(site.hash);
+    let outer = hash_color(hash_u32(site.hash ^ 0xDAA6_6D2B));
+    (
+        lerp(inner.0, outer.0, t),
+        lerp(inner.1, outer.1, t),
+        lerp(inner.2, outer.2, t),
+    )
+}
+
+#[derive(Clone, Copy)]
+struct CellUvSettings {
+    scale: f32,
+    // Shift of the UV centre from the site, in Voronoi space.
+    offset: (f32, f32),
+}
+
+impl CellUvSettings {
+    // Offset (dx, dy) from the nearest site mapped into 0..1, with half the F2
+    // distance standing in for the cell radius.
+    fn uv(&self, dx: f32, dy: f32, d2: f32) -> (f32, f32) {
+        let radius = (d2 * 0.5).max(1.0e-6);
+        let k = 0.5 * self.scale / radius;
+        let fit = |t: f32| {
+            if t.is_finite() {
+                t.clamp(0.0, 1.0)
+            } else {
+                0.5
+            }
+        };
+        (
+            fit(0.5 + (dx - self.offset.0) * k),
+            fit(0.5 + (dy - self.offset.1) * k),
+        )
+    }
+}
+
+// The offset param is in pixels, so it is brought into Voronoi space here.
+fn cell_uv_settings(
+    params: &Parameters<Params>,
+    inv_cell_x: f32,
+    inv_cell_y: f32,
+) -> Result<CellUvSettings, Error> {
+    let scale = params.get(Params::CellUVScale)?.as_float_slider()?.value() as f32;
+    let offset_param = params.get(Params::CellUVOffset)?;
+    let (ox, oy) = point_value_f32(&offset_param.as_point()?);
+    Ok(CellUvSettings {
+        scale: scale.clamp(0.0, 4.0),
+        offset: (ox * inv_cell_x, oy * inv_cell_y),
+    })
+}
+
+// Stable per-cell random value in 0..1, independent of the cell colour.
+fn cell_random(hash: u32) -> f32 {
+    rand01(hash_u32(hash ^ 0x4F6C_DD1D))
+}
+
+#[derive(Clone, Copy)]
+struct RevealSettings {
+    progress: f32,
+    fade: f32,
+}
+
+impl RevealSettings {
+    // Cells appear in cell_random order as progress runs 0..1. A non-zero fade
+    // ramps each cell in over that span, stretched so 0 and 1 stay all-off and
+    // all-on.
+    fn amount(&self, hash: u32) -> f32 {
+        let r = cell_random(hash);
+        if self.fade <= 0.0 {
+            return if r < self.progress || self.progress >= 1.0 {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        ((self.progress * (1.0 + self.fade) - r) / self.fade).clamp(0.0, 1.0)
+    }
+}
+
+fn reveal_settings(params: &Parameters<Params>) -> Result<RevealSettings, Error> {
+    let progress = params
+        .get(Params::RevealProgress)?
+        .as_float_slider()?
+        .value() as f32;
+    let fade = params.get(Params::RevealFade)?.as_float_slider()?.value() as f32;
+    Ok(RevealSettings {
+        progress: progress.clamp(0.0, 1.0),
+        fade: fade.clamp(0.0, 1.0),
+    })
+}
+
 fn hash_color(h: u32) -> (f32, f32, f32) {
     let r = rand01(hash_u32(h ^ 0xB529_7A4D));
     let g = rand01(hash_u32(h ^ 0x68E3_1DA4));
@@ -785,17 +3216,301 @@ fn hash3(x: i32, y: i32, w: i32, seed: u32) -> u32 {
     hash_u32(h)
 }
 
-fn hash_u32(mut x: u32) -> u32 {
-    x ^= x >> 16;
-    x = x.wrapping_mul(0x7FEB_352D);
-    x ^= x >> 15;
-    x = x.wrapping_mul(0x846C_A68B);
-    x ^= x >> 16;
-    x
+// Three nearest sites over a 5x5x3 lattice neighbourhood, closest first.
+fn find_three_nearest<F>(
+    px: f32,
+    py: f32,
+    pw: f32,
+    site_at: F,
+    metric: DistanceMetric,
+    lp_exp: [f32; 3],
+) -> [Site; 3]
+where
+    F: Fn(i32, i32, i32) -> Site,
+{
+    let cell_x = px.floor() as i32;
+    let cell_y = py.floor() as i32;
+    let cell_w = pw.floor() as i32;
+    let mut best = [(f32::INFINITY, Site::default()); 3];
+
+    for nw in (cell_w - 1)..=(cell_w + 1) {
+        for ny in (cell_y - 2)..=(cell_y + 2) {
+            for nx in (cell_x - 2)..=(cell_x + 2) {
+                let site = site_at(nx, ny, nw);
+                let d = metric_distance(px - site.x, py - site.y, pw - site.w, metric, lp_exp);
+                if d < best[2].0 {
+                    best[2] = (d, site);
+                    if best[2].0 < best[1].0 {
+                        best.swap(1, 2);
+                    }
+                    if best[1].0 < best[0].0 {
+                        best.swap(0, 1);
+                    }
+                }
+            }
+        }
+    }
+
+    [best[0].1, best[1].1, best[2].1]
+}
+
+// Draws every Delaunay edge over an opaque black RGBA buffer. Two sites are
+// joined when they own horizontally or vertically adjacent pixels. Endpoints are
+// mapped back to pixels without the wave or noise warp, so lines stay straight.
+fn mesh_triangulation(
+    samples: &[SiteSample],
+    lattice: &Lattice,
+    w: usize,
+    h: usize,
+    width: f32,
+    color: [f32; 4],
+) -> Vec<f32> {
+    let mut output = [0.0, 0.0, 0.0, 1.0].repeat(w * h);
+    let mut edges: std::collections::HashMap<(u32, u32), (Site, Site)> =
+        std::collections::HashMap::new();
+    let mut link = |a: Site, b: Site| {
+        if a.hash != b.hash {
+            let key = (a.hash.min(b.hash), a.hash.max(b.hash));
+            edges.entry(key).or_insert((a, b));
+        }
+    };
+    for y in 0..h {
+        for x in 0..w {
+            let here = samples[y * w + x].nearest;
+            if x + 1 < w {
+                link(here, samples[y * w + x + 1].nearest);
+            }
+            if y + 1 < h {
+                link(here, samples[(y + 1) * w + x].nearest);
+            }
+        }
+    }
+
+    let to_pixel = |site: &Site| {
+        (
+            site.x / lattice.inv_cell_x + lattice.offset_x - lattice.origin_x - 0.5,
+            site.y / lattice.inv_cell_y + lattice.offset_y - lattice.origin_y - 0.5,
+        )
+    };
+    for (a, b) in edges.values() {
+        rasterize_segment(&mut output, w, h, to_pixel(a), to_pixel(b), width, color);
+    }
+    output
+}
+
+// Composites an anti-aliased line of `width` pixels into an RGBA buffer.
+fn rasterize_segment(
+    output: &mut [f32],
+    img_w: usize,
+    img_h: usize,
+    (x0, y0): (f32, f32),
+    (x1, y1): (f32, f32),
+    width: f32,
+    color: [f32; 4],
+) {
+    if img_w == 0 || img_h == 0 || ![x0, y0, x1, y1, width].iter().all(|v| v.is_finite()) {
+        return;
+    }
+    let half = width.max(0.0) * 0.5;
+    // Lines thinner than a pixel fade out instead of aliasing.
+    let strength = color[3] * width.clamp(0.0, 1.0);
+    let reach = half + 1.0;
+    let min_x = (x0.min(x1) - reach).floor().max(0.0) as usize;
+    let min_y = (y0.min(y1) - reach).floor().max(0.0) as usize;
+    let max_x = (x0.max(x1) + reach).ceil().min((img_w - 1) as f32);
+    let max_y = (y0.max(y1) + reach).ceil().min((img_h - 1) as f32);
+    if max_x < 0.0 || max_y < 0.0 {
+        return;
+    }
+
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let len_sq = dx * dx + dy * dy;
+    for y in min_y..=max_y as usize {
+        for x in min_x..=max_x as usize {
+            let (px, py) = (x as f32 - x0, y as f32 - y0);
+            let t = if len_sq > 1.0e-12 {
+                ((px * dx + py * dy) / len_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let d = ((px - dx * t).powi(2) + (py - dy * t).powi(2)).sqrt();
+            let a = strength * (1.0 - smoothstep01(d - half + 0.5));
+            if a <= 0.0 {
+                continue;
+            }
+            let i = (y * img_w + x) * 4;
+            for c in 0..3 {
+                output[i + c] += (color[c] - output[i + c]) * a;
+            }
+            output[i + 3] += (1.0 - output[i + 3]) * a;
+        }
+    }
+}
+
+// Line coverage of the triangle spanned by the three nearest sites.
+fn delaunay_edge_value(px: f32, py: f32, sites: &[Site; 3], thickness: f32, softness: f32) -> f32 {
+    let d = segment_distance(px, py, &sites[0], &sites[1])
+        .min(segment_distance(px, py, &sites[0], &sites[2]))
+        .min(segment_distance(px, py, &sites[1], &sites[2]));
+    if !d.is_finite() {
+        return 0.0;
+    }
+    if softness <= 0.0 {
+        return if d <= thickness { 1.0 } else { 0.0 };
+    }
+    1.0 - smoothstep01((d - thickness) / softness)
+}
+
+fn segment_distance(px: f32, py: f32, a: &Site, b: &Site) -> f32 {
+    let abx = b.x - a.x;
+    let aby = b.y - a.y;
+    let len2 = abx * abx + aby * aby;
+    let t = if len2 > 0.0 {
+        (((px - a.x) * abx + (py - a.y) * aby) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let dx = px - (a.x + abx * t);
+    let dy = py - (a.y + aby * t);
+    (dx * dx + dy * dy).sqrt()
+}
+
+struct StippleSettings {
+    radius: f32,
+    softness: f32,
+    color: PixelF32,
+    site_color: bool,
+}
+
+impl StippleSettings {
+    fn dot_color(&self, site_color: PixelF32) -> PixelF32 {
+        if self.site_color {
+            site_color
+        } else {
+            self.color
+        }
+    }
+}
+
+fn stipple_settings(params: &Parameters<Params>) -> Result<StippleSettings, Error> {
+    Ok(StippleSettings {
+        radius: params
+            .get(Params::StippleRadius)?
+            .as_float_slider()?
+            .value() as f32,
+        softness: params
+            .get(Params::StippleSoftness)?
+            .as_float_slider()?
+            .value() as f32,
+        color: params
+            .get(Params::StippleColor)?
+            .as_color()?
+            .float_value()?,
+        site_color: params.get(Params::StippleSiteColor)?.as_checkbox()?.value(),
+    })
+}
+
+// Dot coverage around a site, measured in lattice units so dots follow Scale X/Y.
+fn stipple_coverage(dx: f32, dy: f32, radius: f32, softness: f32) -> f32 {
+    let d = (dx * dx + dy * dy).sqrt();
+    if !d.is_finite() {
+        return 0.0;
+    }
+    if softness <= 0.0 {
+        return if d <= radius { 1.0 } else { 0.0 };
+    }
+    1.0 - smoothstep01((d - radius) / softness)
+}
+
+fn stipple_pixel(src_px: PixelF32, dot: PixelF32, coverage: f32) -> PixelF32 {
+    let t = if coverage.is_finite() {
+        coverage.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    PixelF32 {
+        alpha: lerp(src_px.alpha, 1.0, t),
+        red: lerp(src_px.red, dot.red, t),
+        green: lerp(src_px.green, dot.green, t),
+        blue: lerp(src_px.blue, dot.blue, t),
+    }
+}
+
+// Source colour with `amount` as alpha, or scaling the source alpha when it is
+// kept. Pixels are premultiplied, so RGB follows the new alpha.
+fn reveal_pixel(src_px: PixelF32, amount: f32, use_original_alpha: bool) -> PixelF32 {
+    let t = if amount.is_finite() {
+        amount.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let src_alpha = if src_px.alpha.is_finite() {
+        src_px.alpha.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let alpha = if use_original_alpha { src_alpha * t } else { t };
+    let k = if src_alpha > 0.0 {
+        alpha / src_alpha
+    } else {
+        0.0
+    };
+    PixelF32 {
+        alpha,
+        red: src_px.red * k,
+        green: src_px.green * k,
+        blue: src_px.blue * k,
+    }
+}
+
+fn boundary_pixel(src_px: PixelF32, edge: f32, thickness: f32) -> PixelF32 {
+    if edge.is_finite() && edge < thickness {
+        src_px
+    } else {
+        PixelF32 {
+            alpha: 0.0,
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        }
+    }
 }
 
-fn rand01(h: u32) -> f32 {
-    h as f32 / u32::MAX as f32
+// `frame` is (width, height, offset x, offset y) of the output layer. Raw pixel
+// coordinates only fit in float worlds, so 8/16bpc falls back to normalized.
+fn encode_position(
+    u: f32,
+    v: f32,
+    encoding: PositionEncoding,
+    frame: (f32, f32, f32, f32),
+    out_is_f32: bool,
+    clamp_32: bool,
+) -> (f32, f32) {
+    let (width, height, offset_x, offset_y) = frame;
+    match encoding {
+        PositionEncoding::Raw if out_is_f32 => {
+            let x = u * width + offset_x;
+            let y = v * height + offset_y;
+            (
+                if x.is_finite() { x } else { 0.0 },
+                if y.is_finite() { y } else { 0.0 },
+            )
+        }
+        PositionEncoding::Centered => {
+            // Clamp (32bpc) bounds the range to -0.5..0.5 rather than 0..1. Integer
+            // depths cannot store negatives, so they keep the +0.5 bias and mid-grey
+            // marks the cell centre.
+            let bias = if out_is_f32 { 0.5 } else { 0.0 };
+            (
+                sanitize_value(u, out_is_f32, clamp_32) - bias,
+                sanitize_value(v, out_is_f32, clamp_32) - bias,
+            )
+        }
+        _ => (
+            sanitize_value(u, out_is_f32, clamp_32),
+            sanitize_value(v, out_is_f32, clamp_32),
+        ),
+    }
 }
 
 fn smooth_blend(d1: f32, d2: f32, smoothness: f32) -> f32 {
@@ -837,3 +3552,130 @@ fn read_pixel_f32(layer: &Layer, world_type: ae::aegp::WorldType, x: usize, y: u
         ae::aegp::WorldType::F32 | ae::aegp::WorldType::None => *layer.as_pixel32(x, y),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unjittered lattice of 1 px cells with the W slice through the site centres.
+    fn grid_lattice() -> Lattice {
+        Lattice {
+            inv_cell_x: 1.0,
+            inv_cell_y: 1.0,
+            inv_cell_w: 1.0,
+            randomness: 0.0,
+            seed: 1,
+            metric: DistanceMetric::Euclidean,
+            lp_exp: [2.0; 3],
+            offset_x: 0.0,
+            offset_y: 0.0,
+            w_value: 0.5,
+            wave_amount: 0.0,
+            wave_frequency: 0.0,
+            wave_angle: 0.0,
+            motion: SiteMotion::default(),
+            min_site_distance: 0.0,
+            repulsion_iters: 1,
+            origin_x: 0.0,
+            origin_y: 0.0,
+            wide_search: false,
+            warp_amount: 0.0,
+            warp_scale: 1.0,
+        }
+    }
+
+    #[test]
+    fn exported_cells_of_a_regular_grid_are_unit_squares() {
+        let export = cells_export(&grid_lattice(), 4, 3, 0.0);
+        assert_eq!(export.cells.len(), 12);
+        for cell in &export.cells {
+            let [ix, iy, iw] = cell.id;
+            assert_eq!(iw, 0);
+            assert_eq!((cell.x, cell.y), (ix as f32 + 0.5, iy as f32 + 0.5));
+
+            let (x0, y0) = (ix as f32, iy as f32);
+            let mut corners = cell.polygon.clone();
+            corners.sort_by(|a, b| a[0].total_cmp(&b[0]).then(a[1].total_cmp(&b[1])));
+            let expected = [
+                [x0, y0],
+                [x0, y0 + 1.0],
+                [x0 + 1.0, y0],
+                [x0 + 1.0, y0 + 1.0],
+            ];
+            assert_eq!(corners.len(), 4, "cell {:?}", cell.id);
+            for (got, want) in corners.iter().zip(expected) {
+                assert!((got[0] - want[0]).abs() < 1.0e-4 && (got[1] - want[1]).abs() < 1.0e-4);
+            }
+
+            let mut neighbors = cell.neighbors.clone();
+            neighbors.sort();
+            let mut expected = vec![
+                [ix - 1, iy, 0],
+                [ix + 1, iy, 0],
+                [ix, iy - 1, 0],
+                [ix, iy + 1, 0],
+            ];
+            expected.sort();
+            assert_eq!(neighbors, expected);
+        }
+    }
+    #[test]
+    fn exported_neighbors_are_mutual_for_jittered_sites() {
+        let lattice = Lattice {
+            randomness: 1.0,
+            seed: 7,
+            ..grid_lattice()
+        };
+        let export = cells_export(&lattice, 8, 6, 0.0);
+        assert!(!export.cells.is_empty());
+        for cell in &export.cells {
+            for id in &cell.neighbors {
+                if let Some(other) = export.cells.iter().find(|c| c.id == *id) {
+                    assert!(
+                        other.neighbors.contains(&cell.id),
+                        "{:?} / {:?}",
+                        cell.id,
+                        id
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn relaxed_sites_keep_the_minimum_distance() {
+        let lattice = Lattice {
+            randomness: 1.0,
+            seed: 3,
+            min_site_distance: 0.6,
+            repulsion_iters: 3,
+            ..grid_lattice()
+        };
+        let table = lattice.site_table(6, 5, None).expect("site table");
+        let count = table.sites.len();
+        for i in 0..count {
+            for j in (i + 1)..count {
+                let (a, b) = (table.sites[i], table.sites[j]);
+                let dist = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.w - b.w).powi(2)).sqrt();
+                assert!(dist >= 0.6 - 1.0e-5, "sites {i} and {j} are {dist} apart");
+            }
+        }
+        // The per-lookup slow path agrees with the table.
+        let [nx, ny, nw] = table.origin;
+        let site = lattice.relaxed_site(nx + 2, ny + 1, nw);
+        let cached = table.get(nx + 2, ny + 1, nw).expect("cell in table");
+        assert_eq!((site.x, site.y, site.w), (cached.x, cached.y, cached.w));
+    }
+
+    #[test]
+    fn only_current_sequence_data_skips_the_legacy_origin_migration() {
+        for version in [0, 1] {
+            let old = Instance::unflatten(version, &[]).expect("unflatten");
+            assert!(old.legacy_origin_pending);
+            assert_eq!(old.flatten().expect("flatten").0, INSTANCE_VERSION - 1);
+        }
+        let current = Instance::unflatten(INSTANCE_VERSION, &[]).expect("unflatten");
+        assert!(!current.legacy_origin_pending);
+        assert_eq!(current.flatten().expect("flatten").0, INSTANCE_VERSION);
+    }
+}